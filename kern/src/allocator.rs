@@ -46,6 +46,15 @@ impl Allocator {
         let (start, end) = memory_map().expect("failed to find memory map");
         *self.0.lock() = Some(AllocatorImpl::new(start, end));
     }
+
+    /// Returns the number of bytes of memory currently allocated.
+    pub fn used(&self) -> usize {
+        self.0
+            .lock()
+            .as_ref()
+            .expect("allocator uninitialized")
+            .used()
+    }
 }
 
 unsafe impl GlobalAlloc for Allocator {
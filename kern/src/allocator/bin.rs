@@ -23,6 +23,8 @@ pub struct Allocator {
     start: usize,
     end: usize,
     bins: [LinkedList; BINS_LEN],
+    /// The number of bytes of the blocks currently allocated.
+    used: usize,
 }
 
 impl Allocator {
@@ -33,8 +35,14 @@ impl Allocator {
             bins: [LinkedList::new(); BINS_LEN],
             start,
             end,
+            used: 0,
         }
     }
+
+    /// Returns the number of bytes currently allocated, counting whole blocks.
+    pub fn used(&self) -> usize {
+        self.used
+    }
 }
 
 const fn bin_index_size(index: usize) -> usize {
@@ -93,6 +101,7 @@ impl LocalAlloc for Allocator {
                     if i > bin_index {
                         self.bins[i - 1].push((addr + this_bin_size / 2) as *mut usize);
                     }
+                    self.used += bin_size;
                     return addr as *mut u8;
                 }
             }
@@ -105,6 +114,7 @@ impl LocalAlloc for Allocator {
             core::ptr::null_mut()
         } else {
             self.start = end;
+            self.used += bin_size;
             // kprintln!("B return addr from pool {:?}", start as *mut u8);
             start as *mut u8
         }
@@ -125,9 +135,10 @@ impl LocalAlloc for Allocator {
     /// behavior.
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         let size = layout.size();
-        let (bin_index, _bin_size) = map_size_bin(size);
+        let (bin_index, bin_size) = map_size_bin(size);
         // kprintln!("DBG dealloc {:?}", ptr);
         self.bins[bin_index].push(ptr as *mut usize);
+        self.used -= bin_size;
     }
 }
 
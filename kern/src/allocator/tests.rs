@@ -292,6 +292,27 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(@bin, bin_used, 65536, |(_, _, mut a)| {
+        assert_eq!(a.used(), 0);
+
+        let small = a.alloc(layout!(24, 8));
+        let large = a.alloc(layout!(1024, 1024));
+        assert!(!small.is_null() && !large.is_null());
+        assert_eq!(a.used(), 32 + 1024);
+
+        a.dealloc(large, layout!(1024, 1024));
+        assert_eq!(a.used(), 32);
+
+        // blocks reused from a larger bin count with the requested size
+        let reused = a.alloc(layout!(512, 8));
+        assert!(!reused.is_null());
+        assert_eq!(a.used(), 32 + 512);
+
+        a.dealloc(reused, layout!(512, 8));
+        a.dealloc(small, layout!(24, 8));
+        assert_eq!(a.used(), 0);
+    });
 }
 
 mod linked_list {
@@ -35,6 +35,11 @@ impl Console {
         self.inner().read_byte()
     }
 
+    /// Returns `true` if a byte is available to be read from the UART device.
+    pub fn has_byte(&mut self) -> bool {
+        self.inner().has_byte()
+    }
+
     /// Writes the byte `byte` to the UART device.
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write_byte(byte)
@@ -1,15 +1,15 @@
+pub mod devfs;
+pub mod procfs;
+pub mod ramdisk;
 pub mod sd;
+pub mod vfs;
 
 use alloc::rc::Rc;
 use core::fmt::{self, Debug};
-use shim::io;
-use shim::ioerr;
-use shim::path::Path;
 
 pub use fat32::traits;
-use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
+use fat32::vfat::{VFat, VFatHandle};
 
-use self::sd::Sd;
 use crate::mutex::Mutex;
 
 #[derive(Clone)]
@@ -38,38 +38,3 @@ impl VFatHandle for PiVFatHandle {
         f(&mut self.0.lock())
     }
 }
-pub struct FileSystem(Mutex<Option<PiVFatHandle>>);
-
-impl FileSystem {
-    /// Returns an uninitialized `FileSystem`.
-    ///
-    /// The file system must be initialized by calling `initialize()` before the
-    /// first memory allocation. Failure to do will result in panics.
-    pub const fn uninitialized() -> Self {
-        FileSystem(Mutex::new(None))
-    }
-
-    /// Initializes the file system.
-    /// The caller should assure that the method is invoked only once during the
-    /// kernel initialization.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the underlying disk or file sytem failed to initialize.
-    pub unsafe fn initialize(&self) {
-        let mut sd = unsafe { Sd::new() }.unwrap();
-        let handle = VFat::<PiVFatHandle>::from_mbr_part0(sd).unwrap();
-        *self.0.lock() = Some(handle);
-    }
-}
-
-// FIXME: Implement `fat32::traits::FileSystem` for `&FileSystem`
-impl fat32::traits::FileSystem for &FileSystem {
-    type File = File<PiVFatHandle>;
-    type Dir = Dir<PiVFatHandle>;
-    type Entry = Entry<PiVFatHandle>;
-
-    fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::Entry> {
-        self.0.lock().as_ref().unwrap().open(path)
-    }
-}
@@ -0,0 +1,144 @@
+use alloc::vec::Vec;
+
+use fat32::vfat::{CacheStats, FsStats};
+use shim::io::{self, Read, Write};
+use shim::ioerr;
+use shim::path::Path;
+
+use crate::console::CONSOLE;
+use crate::fs::vfs::{Dir, DynDir, DynFileSystem, Entry, File, Metadata, Node};
+
+/// A character device exposed in `/dev`.
+#[derive(Debug, Clone, Copy)]
+enum Device {
+    /// The UART console.
+    Console,
+    /// Discards writes and reads as empty.
+    Null,
+    /// Discards writes and reads as zeroes.
+    Zero,
+}
+
+const DEVICES: [(&str, Device); 3] = [
+    ("console", Device::Console),
+    ("null", Device::Null),
+    ("zero", Device::Zero),
+];
+
+/// An open device file. Device files have no size and ignore seeks.
+#[derive(Debug)]
+pub struct DeviceFile(Device);
+
+impl io::Read for DeviceFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0 {
+            Device::Console => CONSOLE.lock().read(buf),
+            Device::Null => Ok(0),
+            Device::Zero => {
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+}
+
+impl io::Write for DeviceFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0 {
+            Device::Console => CONSOLE.lock().write(buf),
+            Device::Null | Device::Zero => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for DeviceFile {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl fat32::traits::File for DeviceFile {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        0
+    }
+}
+
+fn device_entry(name: &str, device: Device) -> Entry {
+    Entry::new(
+        name,
+        Metadata::default(),
+        Node::File(File::new(DeviceFile(device))),
+    )
+}
+
+/// The root directory of `/dev`.
+struct DevDir;
+
+impl DynDir for DevDir {
+    fn entries(&self) -> io::Result<Vec<Entry>> {
+        Ok(DEVICES
+            .iter()
+            .map(|&(name, device)| device_entry(name, device))
+            .collect())
+    }
+}
+
+/// The device file system, usually mounted at `/dev`. It holds a flat,
+/// read-only list of character devices.
+#[derive(Debug)]
+pub struct DevFs;
+
+impl DynFileSystem for DevFs {
+    fn open(&self, path: &Path) -> io::Result<Entry> {
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => {
+                return Ok(Entry::new(
+                    "/",
+                    Metadata::default(),
+                    Node::Dir(Dir::new(DevDir)),
+                ))
+            }
+        };
+        match DEVICES.iter().find(|&&(device, _)| name == device) {
+            Some(&(name, device)) if path.parent() == Some(Path::new("/")) => {
+                Ok(device_entry(name, device))
+            }
+            _ => ioerr!(NotFound, "no such device"),
+        }
+    }
+
+    fn create_file(&self, _path: &Path) -> io::Result<File> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn create_dir(&self, _path: &Path) -> io::Result<Dir> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn statfs(&self) -> io::Result<FsStats> {
+        ioerr!(Other, "statfs is not supported")
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
@@ -0,0 +1,170 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+
+use fat32::vfat::{CacheStats, FsStats};
+use shim::io::{self, SeekFrom};
+use shim::ioerr;
+use shim::path::Path;
+
+use crate::fs::vfs::{Dir, DynDir, DynFileSystem, Entry, File, Metadata, Node};
+
+/// A read-only file whose contents are held in memory.
+#[derive(Debug)]
+pub struct MemFile {
+    data: Vec<u8>,
+    pos: u64,
+}
+
+impl MemFile {
+    pub fn new(data: Vec<u8>) -> MemFile {
+        MemFile { data, pos: 0 }
+    }
+}
+
+impl io::Read for MemFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = core::cmp::min(self.pos as usize, self.data.len());
+        let len = core::cmp::min(buf.len(), self.data.len() - start);
+        buf[..len].copy_from_slice(&self.data[start..start + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl io::Write for MemFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        ioerr!(PermissionDenied, "read-only file")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Seek for MemFile {
+    /// Seeks to offset `pos` in the file. As for FAT files, a seek before the
+    /// start or beyond the end of the file returns an `InvalidInput` error.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(i) => (self.data.len() as i64).saturating_add(i),
+            SeekFrom::Current(i) => (self.pos as i64).saturating_add(i),
+        };
+        if pos < 0 || pos > self.data.len() as i64 {
+            return ioerr!(InvalidInput, "seek outside file");
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl fat32::traits::File for MemFile {
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+const FILES: [&str; 3] = ["meminfo", "mounts", "uptime"];
+
+/// Returns the current contents of the process file system file `name`.
+fn contents(name: &str) -> String {
+    let mut out = String::new();
+    match name {
+        "meminfo" => {
+            let _ = writeln!(out, "heap {}", crate::ALLOCATOR.used());
+        }
+        "mounts" => {
+            for mount in crate::VFS.mounts() {
+                let _ = write!(out, "{} {}", mount.path.display(), mount.fs_type);
+                let _ = match mount.fs.statfs() {
+                    Ok(stats) => writeln!(
+                        out,
+                        " {}K {}K",
+                        stats.total_space() / 1024,
+                        stats.free_space() / 1024
+                    ),
+                    Err(_) => writeln!(out, " - -"),
+                };
+            }
+        }
+        "uptime" => {
+            let uptime = pi::timer::current_time();
+            let _ = writeln!(out, "{}.{:03}", uptime.as_secs(), uptime.subsec_millis());
+        }
+        _ => unreachable!("unknown /proc file"),
+    }
+    out
+}
+
+fn proc_entry(name: &str) -> Entry {
+    let file = MemFile::new(contents(name).into_bytes());
+    Entry::new(name, Metadata::default(), Node::File(File::new(file)))
+}
+
+/// The root directory of `/proc`.
+struct ProcDir;
+
+impl DynDir for ProcDir {
+    fn entries(&self) -> io::Result<Vec<Entry>> {
+        Ok(FILES.iter().map(|name| proc_entry(name)).collect())
+    }
+}
+
+/// The process file system, usually mounted at `/proc`. Its read-only files
+/// report kernel state, generated when the file is opened:
+///
+///   * `meminfo`: `heap` followed by the number of bytes of kernel heap in
+///     use.
+///   * `mounts`: one line per mounted file system with its mount point, type,
+///     and total and free space in KiB if it reports them.
+///   * `uptime`: seconds since the system timer started.
+#[derive(Debug)]
+pub struct ProcFs;
+
+impl DynFileSystem for ProcFs {
+    fn open(&self, path: &Path) -> io::Result<Entry> {
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => {
+                return Ok(Entry::new(
+                    "/",
+                    Metadata::default(),
+                    Node::Dir(Dir::new(ProcDir)),
+                ))
+            }
+        };
+        match FILES.iter().find(|&&file| name == file) {
+            Some(file) if path.parent() == Some(Path::new("/")) => Ok(proc_entry(file)),
+            _ => ioerr!(NotFound, "no such file"),
+        }
+    }
+
+    fn create_file(&self, _path: &Path) -> io::Result<File> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn create_dir(&self, _path: &Path) -> io::Result<Dir> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        ioerr!(PermissionDenied, "read-only file system")
+    }
+
+    fn statfs(&self) -> io::Result<FsStats> {
+        ioerr!(Other, "statfs is not supported")
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
@@ -0,0 +1,89 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+use fat32::traits::BlockDevice;
+use shim::io;
+use shim::ioerr;
+
+const SECTOR_SIZE: usize = 512;
+/// Number of sectors allocated at once.
+const CHUNK_SECTORS: usize = 64;
+const CHUNK_SIZE: usize = CHUNK_SECTORS * SECTOR_SIZE;
+
+/// A block device backed by kernel memory.
+///
+/// Memory is allocated in chunks on the first write of non-zero data to the
+/// chunk, so a freshly formatted disk only uses memory for the file system
+/// metadata. Unallocated chunks read as zeroes.
+pub struct RamDisk {
+    chunks: Vec<Option<Box<[u8]>>>,
+    sectors: u64,
+}
+
+impl RamDisk {
+    /// Returns a zeroed RAM disk of `sectors` 512-byte sectors.
+    pub fn new(sectors: u64) -> RamDisk {
+        let chunks = (sectors as usize + CHUNK_SECTORS - 1) / CHUNK_SECTORS;
+        RamDisk {
+            chunks: (0..chunks).map(|_| None).collect(),
+            sectors,
+        }
+    }
+
+    /// Returns the number of bytes of memory holding sector data.
+    pub fn allocated(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_some()).count() * CHUNK_SIZE
+    }
+
+    fn check_sector(&self, n: u64) -> io::Result<()> {
+        if n >= self.sectors {
+            return ioerr!(InvalidInput, "sector out of range");
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RamDisk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RamDisk")
+            .field("sectors", &self.sectors)
+            .field("allocated", &self.allocated())
+            .finish()
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_sector(n)?;
+        let len = core::cmp::min(buf.len(), SECTOR_SIZE);
+        let offset = (n as usize % CHUNK_SECTORS) * SECTOR_SIZE;
+        match self.chunks[n as usize / CHUNK_SECTORS] {
+            Some(ref chunk) => buf[..len].copy_from_slice(&chunk[offset..offset + len]),
+            None => {
+                for byte in buf[..len].iter_mut() {
+                    *byte = 0;
+                }
+            }
+        }
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.check_sector(n)?;
+        if buf.len() < SECTOR_SIZE {
+            return ioerr!(UnexpectedEof, "buffer too small");
+        }
+        let offset = (n as usize % CHUNK_SECTORS) * SECTOR_SIZE;
+        let chunk = &mut self.chunks[n as usize / CHUNK_SECTORS];
+        if chunk.is_none() {
+            if buf[..SECTOR_SIZE].iter().all(|&byte| byte == 0) {
+                return Ok(SECTOR_SIZE);
+            }
+            *chunk = Some(alloc::vec![0; CHUNK_SIZE].into_boxed_slice());
+        }
+        let chunk = chunk.as_mut().unwrap();
+        chunk[offset..offset + SECTOR_SIZE].copy_from_slice(&buf[..SECTOR_SIZE]);
+        Ok(SECTOR_SIZE)
+    }
+}
@@ -44,7 +44,7 @@ fn wait_micros(micros: u32) {
 }
 
 /// A handle to an SD card controller.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sd;
 
 impl Sd {
@@ -105,7 +105,59 @@ impl BlockDevice for Sd {
         return Ok(res as usize);
     }
 
+    /// Reads the `count` sectors starting at sector `n` into `buf`. The
+    /// controller library transfers one sector per command, but sectors are
+    /// read straight into `buf` when it is 4-byte aligned instead of going
+    /// through a bounce buffer.
+    ///
+    /// # Errors
+    ///
+    /// The errors are the same as for `read_sector()`.
+    fn read_sectors(&mut self, n: u64, count: usize, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.as_ptr() as usize % 4 != 0 {
+            let mut read_bytes = 0;
+            let mut sector = Sector([0u8; 512]);
+            for (i, chunk) in buf.chunks_mut(512).take(count).enumerate() {
+                self.read_sector(n + i as u64, &mut sector.0)?;
+                let chunk_len = chunk.len();
+                chunk.copy_from_slice(&sector.0[..chunk_len]);
+                read_bytes += chunk_len;
+            }
+            return Ok(read_bytes);
+        }
+        let whole = core::cmp::min(count, buf.len() / 512);
+        if n + whole as u64 > 0x80000000 {
+            return ioerr!(InvalidInput, "n > 0x7fffffff");
+        }
+        for i in 0..whole {
+            let res = unsafe { sd_readsector((n + i as u64) as i32, buf[i * 512..].as_mut_ptr()) };
+            if res == 0 {
+                return match unsafe { sd_err } as i32 {
+                    ERR_SENDING_CMD => ioerr!(BrokenPipe, "sending command"),
+                    ERR_TIMEOUT => ioerr!(TimedOut, "timeout"),
+                    _ => ioerr!(Other, "unknown error"),
+                };
+            }
+        }
+        let mut read_bytes = whole * 512;
+        if whole < count && buf.len() > read_bytes {
+            // A trailing partial sector goes through a bounce buffer.
+            let mut sector = Sector([0u8; 512]);
+            self.read_sector(n + whole as u64, &mut sector.0)?;
+            let tail = buf.len() - read_bytes;
+            buf[read_bytes..].copy_from_slice(&sector.0[..tail]);
+            read_bytes += tail;
+        }
+        Ok(read_bytes)
+    }
+
+    /// Writing sectors is not supported by the SD controller library: the SD
+    /// card is mounted read-only.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `PermissionDenied` is always returned.
     fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+        ioerr!(PermissionDenied, "SD card is read only")
     }
 }
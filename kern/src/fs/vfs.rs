@@ -0,0 +1,679 @@
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use fat32::traits;
+use fat32::vfat::{CacheStats, Date, FatType, FormatOptions, FsStats, Time, Timestamp, VFat};
+use shim::io;
+use shim::ioerr;
+use shim::path::{Component, Path, PathBuf};
+
+use crate::fs::devfs::DevFs;
+use crate::fs::procfs::ProcFs;
+use crate::fs::ramdisk::RamDisk;
+use crate::fs::sd::Sd;
+use crate::fs::PiVFatHandle;
+use crate::mutex::Mutex;
+
+/// Size of the RAM disk mounted at `/tmp`, formatted with FAT16: FAT32 needs a
+/// bit more than 32MiB.
+const TMPFS_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Metadata of a VFS entry, converted from the metadata of the file system
+/// holding the entry.
+#[derive(Debug, Default, Clone)]
+pub struct Metadata {
+    pub read_only: bool,
+    pub hidden: bool,
+    pub created: Timestamp,
+    pub accessed: Timestamp,
+    pub modified: Timestamp,
+}
+
+impl Metadata {
+    fn from_fs<M: traits::Metadata>(metadata: &M) -> Metadata {
+        Metadata {
+            read_only: metadata.read_only(),
+            hidden: metadata.hidden(),
+            created: convert_timestamp(metadata.created()),
+            accessed: convert_timestamp(metadata.accessed()),
+            modified: convert_timestamp(metadata.modified()),
+        }
+    }
+}
+
+/// Converts a timestamp of any file system to a FAT timestamp. Dates before
+/// 1980 are not representable and map to the zero timestamp.
+fn convert_timestamp<T: traits::Timestamp>(ts: T) -> Timestamp {
+    if ts.year() < 1980 {
+        return Timestamp::default();
+    }
+    Timestamp {
+        date: Date::new(ts.year(), ts.month(), ts.day()),
+        time: Time::new(ts.hour(), ts.minute(), ts.second()),
+    }
+}
+
+impl traits::Metadata for Metadata {
+    type Timestamp = Timestamp;
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    fn created(&self) -> Timestamp {
+        self.created
+    }
+
+    fn accessed(&self) -> Timestamp {
+        self.accessed
+    }
+
+    fn modified(&self) -> Timestamp {
+        self.modified
+    }
+}
+
+/// Object safe counterpart of `fat32::traits::File`.
+pub trait DynFile: io::Read + io::Write + io::Seek + Send {
+    fn sync(&mut self) -> io::Result<()>;
+    fn size(&self) -> u64;
+}
+
+impl<F: traits::File + Send> DynFile for F {
+    fn sync(&mut self) -> io::Result<()> {
+        traits::File::sync(self)
+    }
+
+    fn size(&self) -> u64 {
+        traits::File::size(self)
+    }
+}
+
+/// Object safe counterpart of `fat32::traits::Dir`.
+pub trait DynDir: Send {
+    fn entries(&self) -> io::Result<Vec<Entry>>;
+}
+
+/// Object safe counterpart of `fat32::traits::FileSystem`, implemented by
+/// every mountable file system. Paths are absolute and relative to the root
+/// of the file system, not of the VFS.
+pub trait DynFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Entry>;
+    fn create_file(&self, path: &Path) -> io::Result<File>;
+    fn create_dir(&self, path: &Path) -> io::Result<Dir>;
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn statfs(&self) -> io::Result<FsStats>;
+    fn cache_stats(&self) -> Option<CacheStats>;
+}
+
+impl<FS> DynFileSystem for FS
+where
+    FS: traits::FileSystem + 'static,
+    FS::File: Send + 'static,
+    FS::Dir: Send + 'static,
+{
+    fn open(&self, path: &Path) -> io::Result<Entry> {
+        Ok(Entry::from_fs::<FS>(traits::FileSystem::open(self, path)?))
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<File> {
+        Ok(File::new(traits::FileSystem::create_file(self, path)?))
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<Dir> {
+        let dir = traits::FileSystem::create_dir(self, path)?;
+        Ok(Dir::new(FsDir::<FS>(dir)))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        traits::FileSystem::remove(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traits::FileSystem::rename(self, from, to)
+    }
+
+    fn statfs(&self) -> io::Result<FsStats> {
+        traits::FileSystem::statfs(self)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        traits::FileSystem::cache_stats(self)
+    }
+}
+
+/// A directory of a `fat32::traits::FileSystem`.
+struct FsDir<FS: traits::FileSystem>(FS::Dir);
+
+impl<FS> DynDir for FsDir<FS>
+where
+    FS: traits::FileSystem + 'static,
+    FS::File: Send + 'static,
+    FS::Dir: Send + 'static,
+{
+    fn entries(&self) -> io::Result<Vec<Entry>> {
+        use fat32::traits::Dir;
+
+        Ok(self.0.entries()?.map(Entry::from_fs::<FS>).collect())
+    }
+}
+
+/// A file of any mounted file system.
+pub struct File {
+    inner: Box<dyn DynFile>,
+    /// Whether the file is on a read-only mount, and so is never written nor
+    /// synced to its file system.
+    read_only: bool,
+}
+
+impl File {
+    pub fn new<F: DynFile + 'static>(file: F) -> File {
+        File {
+            inner: Box::new(file),
+            read_only: false,
+        }
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("File")
+            .field("size", &self.inner.size())
+            .field("read_only", &self.read_only)
+            .finish()
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl io::Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.read_only {
+            return ioerr!(PermissionDenied, "read-only file system");
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.inner.flush()
+    }
+}
+
+impl io::Seek for File {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl traits::File for File {
+    fn sync(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.inner.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+}
+
+/// A directory of any mounted file system, along with the names of the mount
+/// points right below it.
+pub struct Dir {
+    inner: Option<Box<dyn DynDir>>,
+    mounts: Vec<String>,
+    /// Whether the directory is on a read-only mount, which its entries
+    /// inherit.
+    read_only: bool,
+}
+
+impl Dir {
+    pub fn new<D: DynDir + 'static>(dir: D) -> Dir {
+        Dir {
+            inner: Some(Box::new(dir)),
+            mounts: Vec::new(),
+            read_only: false,
+        }
+    }
+
+    /// Returns a directory with no entries, standing for a missing directory
+    /// on the way to a mount point.
+    fn empty() -> Dir {
+        Dir {
+            inner: None,
+            mounts: Vec::new(),
+            read_only: false,
+        }
+    }
+}
+
+impl fmt::Debug for Dir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dir")
+            .field("mounts", &self.mounts)
+            .field("read_only", &self.read_only)
+            .finish()
+    }
+}
+
+impl traits::Dir for Dir {
+    type Entry = Entry;
+    type Iter = alloc::vec::IntoIter<Entry>;
+
+    /// Returns the entries of the directory followed by an empty directory
+    /// entry for each mount point below it that the directory does not hold.
+    /// The entries do not cross mount points: open mounted directories by
+    /// path instead.
+    fn entries(&self) -> io::Result<Self::Iter> {
+        let mut entries = match self.inner {
+            Some(ref dir) => dir.entries()?,
+            None => Vec::new(),
+        };
+        for entry in entries.iter_mut() {
+            entry.set_read_only(self.read_only);
+        }
+        for name in self.mounts.iter() {
+            if !entries.iter().any(|entry| &entry.name == name) {
+                entries.push(Entry::new(
+                    name,
+                    Metadata::default(),
+                    Node::Dir(Dir::empty()),
+                ));
+            }
+        }
+        Ok(entries.into_iter())
+    }
+}
+
+/// What a VFS entry refers to.
+#[derive(Debug)]
+pub enum Node {
+    File(File),
+    Dir(Dir),
+}
+
+/// An entry of any mounted file system.
+#[derive(Debug)]
+pub struct Entry {
+    name: String,
+    metadata: Metadata,
+    node: Node,
+}
+
+impl Entry {
+    pub fn new(name: &str, metadata: Metadata, node: Node) -> Entry {
+        Entry {
+            name: name.to_string(),
+            metadata,
+            node,
+        }
+    }
+
+    /// Marks the file or directory of the entry as being on a read-only
+    /// mount, or not.
+    fn set_read_only(&mut self, read_only: bool) {
+        match self.node {
+            Node::File(ref mut file) => file.read_only = read_only,
+            Node::Dir(ref mut dir) => dir.read_only = read_only,
+        }
+    }
+
+    fn from_fs<FS>(entry: FS::Entry) -> Entry
+    where
+        FS: traits::FileSystem + 'static,
+        FS::File: Send + 'static,
+        FS::Dir: Send + 'static,
+    {
+        use fat32::traits::Entry;
+
+        let name = entry.name().to_string();
+        let metadata = Metadata::from_fs(entry.metadata());
+        let node = if entry.is_dir() {
+            Node::Dir(Dir::new(FsDir::<FS>(entry.into_dir().unwrap())))
+        } else {
+            Node::File(self::File::new(entry.into_file().unwrap()))
+        };
+        self::Entry {
+            name,
+            metadata,
+            node,
+        }
+    }
+}
+
+impl traits::Entry for Entry {
+    type File = File;
+    type Dir = Dir;
+    type Metadata = Metadata;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        match self.node {
+            Node::File(ref file) => Some(file),
+            Node::Dir(_) => None,
+        }
+    }
+
+    fn as_dir(&self) -> Option<&Dir> {
+        match self.node {
+            Node::Dir(ref dir) => Some(dir),
+            Node::File(_) => None,
+        }
+    }
+
+    fn into_file(self) -> Option<File> {
+        match self.node {
+            Node::File(file) => Some(file),
+            Node::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self) -> Option<Dir> {
+        match self.node {
+            Node::Dir(dir) => Some(dir),
+            Node::File(_) => None,
+        }
+    }
+}
+
+/// A file system mounted in the VFS.
+#[derive(Clone)]
+pub struct MountPoint {
+    /// Normalized absolute path of the mount point.
+    pub path: PathBuf,
+    /// Name of the file system type, as shown in `/proc/mounts`.
+    pub fs_type: &'static str,
+    pub fs: Rc<dyn DynFileSystem>,
+    /// Whether files of the file system can be created, written, removed or
+    /// renamed through the VFS.
+    pub read_only: bool,
+}
+
+// As for `PiVFatHandle`, these impls are unsound: `Rc` is used since atomics
+// require the MMU, which is initialized after the file systems are mounted.
+// Only one core is enabled, so they cause no harm for now.
+unsafe impl Send for MountPoint {}
+unsafe impl Sync for MountPoint {}
+
+impl fmt::Debug for MountPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MountPoint")
+            .field("path", &self.path)
+            .field("fs_type", &self.fs_type)
+            .field("read_only", &self.read_only)
+            .finish()
+    }
+}
+
+/// Returns `path` with `.` and `..` components resolved.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` if `path` is not absolute.
+fn normalize(path: &Path) -> io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir if normalized.as_os_str().is_empty() => normalized.push("/"),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) if !normalized.as_os_str().is_empty() => normalized.push(name),
+            _ => return ioerr!(InvalidInput, "path is not absolute"),
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        return ioerr!(InvalidInput, "path is not absolute");
+    }
+    Ok(normalized)
+}
+
+/// The virtual file system: a mount table of file systems, each attached at
+/// an absolute path. A path is resolved in the file system mounted at its
+/// longest mount point prefix.
+pub struct Vfs(Mutex<Vec<MountPoint>>);
+
+impl Vfs {
+    /// Returns a VFS with no mounted file system.
+    ///
+    /// The VFS must be initialized by calling `initialize()` before the file
+    /// systems are used.
+    pub const fn uninitialized() -> Self {
+        Vfs(Mutex::new(Vec::new()))
+    }
+
+    /// Mounts the kernel file systems: the first FAT partition of the SD card
+    /// at `/`, its second one at `/mnt/sd2` if it has one, the device and
+    /// process file systems at `/dev` and `/proc`, and a RAM disk at `/tmp`.
+    /// The SD card partitions are mounted read-only since the SD controller
+    /// library cannot write sectors.
+    /// The caller should assure that the method is invoked only once during
+    /// the kernel initialization.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the SD card or its first partition failed to initialize.
+    pub unsafe fn initialize(&self) {
+        let sd = Sd::new().unwrap();
+        let root = VFat::<PiVFatHandle>::from(sd.clone()).unwrap();
+        self.mount("/", "vfat", Rc::new(root), true).unwrap();
+        if let Ok(sd2) = VFat::<PiVFatHandle>::from_mbr_part(sd, 1) {
+            self.mount("/mnt/sd2", "vfat", Rc::new(sd2), true).unwrap();
+        }
+        self.mount("/dev", "devfs", Rc::new(DevFs), false).unwrap();
+        self.mount("/proc", "procfs", Rc::new(ProcFs), false).unwrap();
+
+        let sectors = TMPFS_SIZE / 512;
+        let mut disk = RamDisk::new(sectors);
+        let options = FormatOptions {
+            fat_type: FatType::Fat16,
+            volume_label: "TMP".to_string(),
+            ..FormatOptions::default()
+        };
+        fat32::vfat::format(&mut disk, sectors, &options).unwrap();
+        let tmp = VFat::<PiVFatHandle>::from_mbr_part0(disk).unwrap();
+        self.mount("/tmp", "tmpfs", Rc::new(tmp), false).unwrap();
+    }
+
+    /// Mounts `fs` at `path`, read-only if `read_only` is set. The mount point
+    /// does not need to exist in the parent file system.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `AlreadyExists` if a file system is already
+    /// mounted at `path`, or of kind `InvalidInput` if `path` is not absolute.
+    pub fn mount<P: AsRef<Path>>(
+        &self,
+        path: P,
+        fs_type: &'static str,
+        fs: Rc<dyn DynFileSystem>,
+        read_only: bool,
+    ) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        let mut mounts = self.0.lock();
+        if mounts.iter().any(|mount| mount.path == path) {
+            return ioerr!(AlreadyExists, "a file system is already mounted there");
+        }
+        mounts.push(MountPoint {
+            path,
+            fs_type,
+            fs,
+            read_only,
+        });
+        Ok(())
+    }
+
+    /// Unmounts the file system mounted at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotFound` if no file system is mounted at
+    /// `path`, or of kind `Other` if other file systems are mounted below it.
+    pub fn unmount<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        if !self.child_mounts(&path).is_empty() {
+            return ioerr!(Other, "mount point is busy");
+        }
+        let mut mounts = self.0.lock();
+        match mounts.iter().position(|mount| mount.path == path) {
+            Some(index) => {
+                mounts.remove(index);
+                Ok(())
+            }
+            None => ioerr!(NotFound, "no file system mounted there"),
+        }
+    }
+
+    /// Returns the mounted file systems in mount order.
+    pub fn mounts(&self) -> Vec<MountPoint> {
+        self.0.lock().clone()
+    }
+
+    /// Returns the mount point of the file system holding the normalized path
+    /// `path`, and the path relative to the root of that file system.
+    ///
+    /// The mount table is unlocked before returning, so that file systems
+    /// (like `/proc`) can use it.
+    fn resolve(&self, path: &Path) -> io::Result<(MountPoint, PathBuf)> {
+        let mounts = self.0.lock();
+        let mount = mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.path))
+            .max_by_key(|mount| mount.path.components().count());
+        match mount {
+            Some(mount) => {
+                let inner = Path::new("/").join(path.strip_prefix(&mount.path).unwrap());
+                Ok((mount.clone(), inner))
+            }
+            None => ioerr!(NotFound, "no file system mounted"),
+        }
+    }
+
+    /// Same as `resolve()`, but fails with an error of kind `PermissionDenied`
+    /// if the file system is mounted read-only.
+    fn resolve_writable(&self, path: &Path) -> io::Result<(MountPoint, PathBuf)> {
+        let (mount, inner) = self.resolve(path)?;
+        if mount.read_only {
+            return ioerr!(PermissionDenied, "read-only file system");
+        }
+        Ok((mount, inner))
+    }
+
+    /// Returns the names of the children of the normalized path `path` that
+    /// are mount points or lead to one.
+    fn child_mounts(&self, path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for mount in self.0.lock().iter() {
+            let first = match mount.path.strip_prefix(path) {
+                Ok(rest) => rest.components().next(),
+                Err(_) => None,
+            };
+            if let Some(Component::Normal(name)) = first {
+                let name = name.to_str().unwrap_or_default();
+                if !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names
+    }
+}
+
+impl fat32::traits::FileSystem for &Vfs {
+    type File = File;
+    type Dir = Dir;
+    type Entry = Entry;
+
+    fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::Entry> {
+        let path = normalize(path.as_ref())?;
+        let (mount, inner) = self.resolve(&path)?;
+        let mounts = self.child_mounts(&path);
+        let name = match path.file_name() {
+            Some(name) => name.to_str().unwrap_or_default(),
+            None => "/",
+        };
+        let mut entry = match mount.fs.open(&inner) {
+            Ok(entry) => entry,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && !mounts.is_empty() => {
+                Entry::new(name, Metadata::default(), Node::Dir(Dir::empty()))
+            }
+            Err(e) => return Err(e),
+        };
+        if inner == Path::new("/") {
+            entry.name = name.to_string();
+        }
+        entry.set_read_only(mount.read_only);
+        if let Node::Dir(ref mut dir) = entry.node {
+            dir.mounts = mounts;
+        }
+        Ok(entry)
+    }
+
+    fn create_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::File> {
+        let (mount, inner) = self.resolve_writable(&normalize(path.as_ref())?)?;
+        mount.fs.create_file(&inner)
+    }
+
+    fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::Dir> {
+        let (mount, inner) = self.resolve_writable(&normalize(path.as_ref())?)?;
+        mount.fs.create_dir(&inner)
+    }
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = normalize(path.as_ref())?;
+        let (mount, inner) = self.resolve_writable(&path)?;
+        if inner == Path::new("/") || !self.child_mounts(&path).is_empty() {
+            return ioerr!(Other, "mount point is busy");
+        }
+        mount.fs.remove(&inner)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+        let from = normalize(from.as_ref())?;
+        let (mount, inner_from) = self.resolve_writable(&from)?;
+        let (to_mount, inner_to) = self.resolve(&normalize(to.as_ref())?)?;
+        if mount.path != to_mount.path {
+            return ioerr!(Other, "cannot rename across file systems");
+        }
+        if inner_from == Path::new("/") || !self.child_mounts(&from).is_empty() {
+            return ioerr!(Other, "mount point is busy");
+        }
+        mount.fs.rename(&inner_from, &inner_to)
+    }
+
+    /// Returns the usage of the root file system.
+    fn statfs(&self) -> io::Result<FsStats> {
+        let (mount, _) = self.resolve(Path::new("/"))?;
+        mount.fs.statfs()
+    }
+
+    /// Returns the sector cache statistics of the root file system.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        let (mount, _) = self.resolve(Path::new("/")).ok()?;
+        mount.fs.cache_stats()
+    }
+}
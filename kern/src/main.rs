@@ -30,7 +30,7 @@ use allocator::memory_map;
 use allocator::Allocator;
 use console::{kprint, kprintln};
 use fs::sd::Sd;
-use fs::FileSystem;
+use fs::vfs::Vfs;
 use pi::atags::Atags;
 use process::GlobalScheduler;
 use traps::irq::Irq;
@@ -38,7 +38,7 @@ use vm::VMManager;
 
 #[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
-pub static FILESYSTEM: FileSystem = FileSystem::uninitialized();
+pub static VFS: Vfs = Vfs::uninitialized();
 pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
 pub static VMM: VMManager = VMManager::uninitialized();
 pub static IRQ: Irq = Irq::uninitialized();
@@ -47,7 +47,7 @@ fn kmain() -> ! {
     kprintln!("Welcome to cs3210!");
     unsafe {
         ALLOCATOR.initialize();
-        FILESYSTEM.initialize();
+        VFS.initialize();
         IRQ.initialize();
         VMM.initialize();
         SCHEDULER.initialize();
@@ -19,6 +19,13 @@ const_assert_eq!(
     ((1 << USER_MASK_BITS) - 1) << (64 - USER_MASK_BITS)
 );
 pub const USER_STACK_BASE: usize = core::usize::MAX & PAGE_MASK;
+/// Maximum size of a user process's stack. Pages below the top one are mapped
+/// as the stack grows into them.
+pub const USER_STACK_MAX_SIZE: usize = 16 * PAGE_SIZE;
+/// Size of the region below the maximum stack that is never mapped, so a stack
+/// overflow faults instead of running into other memory.
+pub const USER_STACK_GUARD_SIZE: usize = PAGE_SIZE;
+const_assert_eq!(USER_STACK_MAX_SIZE % PAGE_SIZE, 0);
 pub const USER_MAX_VM_SIZE: usize = 0x4000_0000;
 const_assert_eq!(USER_IMG_BASE.wrapping_add(USER_MAX_VM_SIZE), 0);
 pub const USER_MAX_VA: usize = 0xffff_ffff_ffff_ffff;
@@ -1,9 +1,16 @@
+mod elf;
+mod fd;
+mod image;
+mod mmap;
 mod process;
 mod scheduler;
 mod stack;
 mod state;
 
-pub use self::process::{Id, Process};
+pub use self::fd::{Fd, FdTable, OpenFile};
+pub use self::image::Image;
+pub use self::mmap::Mapping;
+pub use self::process::{FaultAction, Id, Process};
 pub use self::scheduler::GlobalScheduler;
 pub use self::stack::Stack;
 pub use self::state::State;
@@ -0,0 +1,156 @@
+use core::fmt;
+use core::mem::size_of;
+use core::ops::Range;
+
+use kernel_api::{OsError, OsResult};
+use shim::const_assert_size;
+
+use crate::allocator::util::{align_down, align_up};
+use crate::param::PAGE_SIZE;
+use crate::vm::PagePerm;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+
+/// Maximum number of program headers of an executable.
+const MAX_PHNUM: u16 = 64;
+
+/// Program header type of a segment loaded in memory.
+pub const PT_LOAD: u32 = 1;
+
+/// Program header flags: the segment is executable or writable.
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+
+/// Returns the permissions of a page holding segments with the program header
+/// flags `flags`. Every segment is readable.
+pub fn page_perm(flags: u32) -> PagePerm {
+    match (flags & PF_W != 0, flags & PF_X != 0) {
+        (true, true) => PagePerm::RWX,
+        (true, false) => PagePerm::RW,
+        (false, true) => PagePerm::RX,
+        (false, false) => PagePerm::RO,
+    }
+}
+
+/// The header at the start of an ELF64 file.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ElfHeader {
+    pub ident: [u8; 16],
+    pub kind: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+const_assert_size!(ElfHeader, 64);
+
+impl ElfHeader {
+    /// Returns `true` if `data` starts with the ELF magic number.
+    pub fn is_elf(data: &[u8]) -> bool {
+        data.starts_with(&ELF_MAGIC)
+    }
+
+    /// Parses the ELF header at the start of `data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::IoErrorInvalidData` if `data` does not start with the
+    /// header of a little-endian ELF64 executable for AArch64, if its program
+    /// headers are smaller than `ProgramHeader`, or if it has more than
+    /// `MAX_PHNUM` of them.
+    pub fn from(data: &[u8]) -> OsResult<ElfHeader> {
+        if data.len() < size_of::<ElfHeader>() || !ElfHeader::is_elf(data) {
+            return Err(OsError::IoErrorInvalidData);
+        }
+        let header = unsafe { *{ data.as_ptr() as *const ElfHeader } };
+        if header.ident[4] != ELFCLASS64
+            || header.ident[5] != ELFDATA2LSB
+            || header.kind != ET_EXEC
+            || header.machine != EM_AARCH64
+            || (header.phentsize as usize) < size_of::<ProgramHeader>()
+            || header.phnum > MAX_PHNUM
+        {
+            return Err(OsError::IoErrorInvalidData);
+        }
+        Ok(header)
+    }
+}
+
+impl fmt::Debug for ElfHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ElfHeader")
+            .field("entry", &{ self.entry })
+            .field("phoff", &{ self.phoff })
+            .field("phentsize", &{ self.phentsize })
+            .field("phnum", &{ self.phnum })
+            .finish()
+    }
+}
+
+/// An ELF64 program header, describing a segment of the program.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+const_assert_size!(ProgramHeader, 56);
+
+impl ProgramHeader {
+    /// Parses the program header at the start of `data`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is shorter than a program header.
+    pub fn from(data: &[u8]) -> ProgramHeader {
+        assert!(data.len() >= size_of::<ProgramHeader>());
+        unsafe { *{ data.as_ptr() as *const ProgramHeader } }
+    }
+
+    /// Returns `true` if the segment is loaded in memory.
+    pub fn is_load(&self) -> bool {
+        self.kind == PT_LOAD
+    }
+
+    /// Returns the range of addresses of the pages the segment spans in
+    /// memory.
+    pub fn pages(&self) -> Range<usize> {
+        let start = align_down(self.vaddr as usize, PAGE_SIZE);
+        let end = align_up((self.vaddr + self.memsz) as usize, PAGE_SIZE);
+        start..end
+    }
+}
+
+impl fmt::Debug for ProgramHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProgramHeader")
+            .field("kind", &{ self.kind })
+            .field("flags", &{ self.flags })
+            .field("offset", &{ self.offset })
+            .field("vaddr", &{ self.vaddr })
+            .field("filesz", &{ self.filesz })
+            .field("memsz", &{ self.memsz })
+            .finish()
+    }
+}
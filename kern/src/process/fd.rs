@@ -0,0 +1,106 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use fat32::traits::FileSystem;
+use kernel_api::{OsError, OsResult};
+
+use crate::fs::vfs::File;
+use crate::mutex::Mutex;
+
+/// Type alias for the type of a file descriptor.
+pub type Fd = u64;
+
+/// Maximum number of files a process can have open at once.
+pub const MAX_OPEN_FILES: usize = 32;
+
+/// An open file. Clones refer to the same file and share its position, as
+/// descriptors inherited through `fork` do.
+#[derive(Clone)]
+pub struct OpenFile(Rc<Mutex<File>>);
+
+// As for `PiVFatHandle`, these impls are unsound: `Rc` is used since atomics
+// require the MMU, and only one core is enabled, so they cause no harm for now.
+unsafe impl Send for OpenFile {}
+unsafe impl Sync for OpenFile {}
+
+impl OpenFile {
+    pub fn new(file: File) -> OpenFile {
+        OpenFile(Rc::new(Mutex::new(file)))
+    }
+
+    /// Executes the provided closure with the file locked.
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut File) -> R,
+    {
+        f(&mut self.0.lock())
+    }
+}
+
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The file descriptor table of a process.
+#[derive(Debug, Clone)]
+pub struct FdTable {
+    files: Vec<Option<OpenFile>>,
+}
+
+impl FdTable {
+    /// Returns a table with no open file.
+    pub fn new() -> FdTable {
+        FdTable { files: Vec::new() }
+    }
+
+    /// Returns a table with the console open as standard input (0), output (1)
+    /// and error (2).
+    pub fn with_stdio() -> OsResult<FdTable> {
+        let console = (&crate::VFS).open_file("/dev/console")?;
+        let console = OpenFile::new(console);
+        Ok(FdTable {
+            files: alloc::vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        })
+    }
+
+    /// Adds `file` at the lowest free descriptor and returns that descriptor.
+    ///
+    /// Fails with `OsError::NoMemory` if `MAX_OPEN_FILES` files are already
+    /// open.
+    pub fn insert(&mut self, file: OpenFile) -> OsResult<Fd> {
+        match self.files.iter().position(|slot| slot.is_none()) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                Ok(fd as Fd)
+            }
+            None if self.files.len() < MAX_OPEN_FILES => {
+                self.files.push(Some(file));
+                Ok((self.files.len() - 1) as Fd)
+            }
+            None => Err(OsError::NoMemory),
+        }
+    }
+
+    /// Returns the file open at `fd`.
+    ///
+    /// Fails with `OsError::BadFileDescriptor` if `fd` is not open.
+    pub fn get(&self, fd: Fd) -> OsResult<OpenFile> {
+        match self.files.get(fd as usize) {
+            Some(Some(file)) => Ok(file.clone()),
+            _ => Err(OsError::BadFileDescriptor),
+        }
+    }
+
+    /// Closes `fd` and returns the file that was open at it.
+    ///
+    /// Fails with `OsError::BadFileDescriptor` if `fd` is not open.
+    pub fn remove(&mut self, fd: Fd) -> OsResult<OpenFile> {
+        match self.files.get_mut(fd as usize) {
+            Some(slot) => slot.take().ok_or(OsError::BadFileDescriptor),
+            None => Err(OsError::BadFileDescriptor),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+
+use kernel_api::{OsError, OsResult};
+use shim::io::{self, Read, Seek, SeekFrom};
+
+use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+use crate::process::elf::{self, ProgramHeader};
+use crate::process::OpenFile;
+use crate::vm::{UserPageTable, VirtualAddr};
+
+/// The program image of a process: the segments of the executable it was
+/// loaded from. Pages of the image are only read from the file the first time
+/// they are accessed, by `load_page()`.
+#[derive(Debug, Clone)]
+pub struct Image {
+    file: OpenFile,
+    segments: Vec<ProgramHeader>,
+}
+
+impl Image {
+    /// Returns the image made of the loadable `segments` of the executable
+    /// `file`.
+    pub fn new(file: OpenFile, segments: Vec<ProgramHeader>) -> Image {
+        Image { file, segments }
+    }
+
+    /// Returns the end of the last page of the image, or `USER_IMG_BASE` if it
+    /// is empty.
+    pub fn end(&self) -> VirtualAddr {
+        let end = self
+            .segments
+            .iter()
+            .map(|segment| segment.pages().end)
+            .max()
+            .unwrap_or(USER_IMG_BASE);
+        VirtualAddr::from(end)
+    }
+
+    /// Maps the page of the image at the page aligned address `page` in
+    /// `vmap` and fills it with the contents of the segments it holds, zeroed
+    /// past their file contents. The page is accessible as each of these
+    /// segments.
+    ///
+    /// Returns `Ok(false)` without mapping anything if `page` is already mapped
+    /// in `vmap` or if no segment spans the page. `OsError::NoMemory` is
+    /// returned if the page cannot be allocated. If the file cannot be read,
+    /// the page is unmapped and the I/O error is returned. The file position
+    /// is not preserved.
+    pub fn load_page(&self, vmap: &mut UserPageTable, page: VirtualAddr) -> OsResult<bool> {
+        if vmap.is_mapped(page, PAGE_SIZE) {
+            return Ok(false);
+        }
+        let start = page.as_usize();
+        let segments: Vec<&ProgramHeader> = self
+            .segments
+            .iter()
+            .filter(|segment| segment.pages().contains(&start))
+            .collect();
+        if segments.is_empty() {
+            return Ok(false);
+        }
+
+        let flags = segments
+            .iter()
+            .fold(0, |flags, segment| flags | segment.flags);
+        let buf = vmap.try_alloc(page, elf::page_perm(flags))?;
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        let result = self.file.with(|file| -> io::Result<()> {
+            for segment in &segments {
+                let vaddr = segment.vaddr as usize;
+                let from = max(vaddr, start);
+                let to = min(vaddr + segment.filesz as usize, start + PAGE_SIZE);
+                if from < to {
+                    file.seek(SeekFrom::Start(segment.offset + (from - vaddr) as u64))?;
+                    file.read_exact(&mut buf[from - start..to - start])?;
+                }
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                vmap.dealloc(page);
+                Err(OsError::from(e))
+            }
+        }
+    }
+}
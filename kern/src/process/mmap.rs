@@ -0,0 +1,180 @@
+use alloc::vec::Vec;
+
+use kernel_api::{OsError, OsResult, PROT_EXEC, PROT_READ, PROT_WRITE};
+use shim::io::{self, Read, Seek, SeekFrom};
+
+use crate::allocator::util::align_up;
+use crate::param::{PAGE_SIZE, USER_MAX_VM_SIZE};
+use crate::process::{OpenFile, Process};
+use crate::vm::{PagePerm, VirtualAddr};
+
+/// A region of user memory mapped with `Process::mmap()`: the pages from
+/// `start` up to `end`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Returns the permissions of pages mapped with the `mmap` protection flags
+/// `prot`, or `OsError::InvalidArgument` if they are empty or unknown.
+fn prot_perm(prot: u64) -> OsResult<PagePerm> {
+    if prot == 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(OsError::InvalidArgument);
+    }
+    Ok(match (prot & PROT_WRITE != 0, prot & PROT_EXEC != 0) {
+        (true, true) => PagePerm::RWX,
+        (true, false) => PagePerm::RW,
+        (false, true) => PagePerm::RX,
+        (false, false) => PagePerm::RO,
+    })
+}
+
+/// Reads from `file` until `buf` is full or the end of the file is reached.
+fn read_page<R: Read>(file: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+impl Process {
+    /// Maps `len` bytes of new memory, rounded up to whole pages, with the
+    /// protection flags `prot`, and returns its address. Regions are placed
+    /// below the stack guard region, downward from the highest free pages.
+    ///
+    /// Without `file`, the memory is zeroed. Otherwise, it holds a copy of the
+    /// contents of `file` from `offset`, zeroed past the end of the file, and
+    /// may not be writable. The file position is left unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::InvalidArgument` if `len` is zero, `prot` is empty or
+    /// unknown, or `offset` is not page aligned; `OsError::NoAccess` if a file
+    /// mapping is writable; `OsError::NoVmSpace` if no region of the user
+    /// address space is free for the mapping; and `OsError::NoMemory` if its
+    /// pages cannot be allocated. I/O errors reading `file` are returned as
+    /// well. Nothing is mapped on error.
+    pub fn mmap(
+        &mut self,
+        len: usize,
+        prot: u64,
+        file: Option<OpenFile>,
+        offset: u64,
+    ) -> OsResult<VirtualAddr> {
+        let perm = prot_perm(prot)?;
+        if len == 0 || offset % PAGE_SIZE as u64 != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        if file.is_some() && prot & PROT_WRITE != 0 {
+            return Err(OsError::NoAccess);
+        }
+        if len > USER_MAX_VM_SIZE {
+            return Err(OsError::NoVmSpace);
+        }
+        let len = align_up(len, PAGE_SIZE);
+        let start = self.find_free_region(len).ok_or(OsError::NoVmSpace)?;
+        let end = start + len;
+
+        let vmap = &mut self.vmap;
+        let mut mapped = start;
+        let result: OsResult<()> = (|| {
+            let position = match file {
+                Some(ref file) => Some(file.with(|file| {
+                    let position = file.seek(SeekFrom::Current(0))?;
+                    file.seek(SeekFrom::Start(offset))?;
+                    Ok::<_, io::Error>(position)
+                })?),
+                None => None,
+            };
+            while mapped < end {
+                let page = vmap.try_alloc(VirtualAddr::from(mapped), perm)?;
+                mapped += PAGE_SIZE;
+                for byte in page.iter_mut() {
+                    *byte = 0;
+                }
+                if let Some(ref file) = file {
+                    file.with(|file| read_page(file, page))?;
+                }
+            }
+            if let (Some(file), Some(position)) = (file, position) {
+                file.with(|file| file.seek(SeekFrom::Start(position)))?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            for page in (start..mapped).step_by(PAGE_SIZE) {
+                vmap.dealloc(VirtualAddr::from(page));
+            }
+            return Err(e);
+        }
+
+        self.mappings.push(Mapping { start, end });
+        Ok(VirtualAddr::from(start))
+    }
+
+    /// Unmaps the pages of the regions mapped with `mmap()` in the `len` bytes
+    /// starting at the page aligned address `addr`, and frees them. Parts of a
+    /// region outside of the range stay mapped.
+    ///
+    /// Returns `OsError::InvalidArgument` if `addr` is not page aligned or
+    /// `len` is zero.
+    pub fn munmap(&mut self, addr: VirtualAddr, len: usize) -> OsResult<()> {
+        let start = addr.as_usize();
+        if start % PAGE_SIZE != 0 || len == 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let end = start.saturating_add(len);
+
+        let mut remaining = Vec::new();
+        for mapping in self.mappings.drain(..) {
+            if mapping.end <= start || mapping.start >= end {
+                remaining.push(mapping);
+                continue;
+            }
+            let first = core::cmp::max(mapping.start, start);
+            let last = core::cmp::min(mapping.end, end);
+            for page in (first..last).step_by(PAGE_SIZE) {
+                self.vmap.dealloc(VirtualAddr::from(page));
+            }
+            if mapping.start < first {
+                remaining.push(Mapping {
+                    start: mapping.start,
+                    end: first,
+                });
+            }
+            if last < mapping.end {
+                remaining.push(Mapping {
+                    start: last,
+                    end: mapping.end,
+                });
+            }
+        }
+        self.mappings = remaining;
+        Ok(())
+    }
+
+    /// Returns the address of the highest region of `len` bytes, a multiple of
+    /// the page size, with no mapped page between the heap and the stack guard
+    /// region.
+    fn find_free_region(&self, len: usize) -> Option<usize> {
+        let floor = align_up(self.brk.as_usize(), PAGE_SIZE);
+        let mut end = Self::get_stack_guard().as_usize();
+        while end >= floor + len {
+            let start = end - len;
+            let highest_mapped = (start..end)
+                .step_by(PAGE_SIZE)
+                .rev()
+                .find(|&page| self.vmap.is_mapped(VirtualAddr::from(page), PAGE_SIZE));
+            match highest_mapped {
+                Some(page) => end = page,
+                None => return Some(start),
+            }
+        }
+        None
+    }
+}
@@ -1,4 +1,5 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::mem;
 use shim::io;
 use shim::path::Path;
@@ -8,7 +9,8 @@ use aarch64;
 use crate::allocator::util::{align_down, align_up};
 use crate::console::{kprint, kprintln};
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::elf::{self, ElfHeader, ProgramHeader};
+use crate::process::{FdTable, Image, Mapping, OpenFile, Stack, State};
 use crate::traps::TrapFrame;
 use crate::vm::*;
 use aarch64::*;
@@ -16,12 +18,26 @@ use core::ptr::Unique;
 use fat32::traits::Entry;
 use fat32::traits::File;
 use fat32::traits::FileSystem;
-use kernel_api::{OsError, OsResult};
-use shim::io::{Read, Seek};
+use kernel_api::{OsError, OsResult, WAIT_ANY};
+use shim::io::{Read, Seek, SeekFrom};
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
 
+/// How a page fault of a user process was handled.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FaultAction {
+    /// The faulting page has been mapped or made writable: the access can be
+    /// retried.
+    Retry,
+    /// The process ran out of stack and accessed the guard region below it.
+    StackOverflow,
+    /// The process accessed memory it may not access.
+    Invalid,
+    /// The page could not be allocated.
+    OutOfMemory,
+}
+
 /// A structure that represents the complete state of a process.
 #[derive(Debug)]
 pub struct Process {
@@ -34,6 +50,23 @@ pub struct Process {
     pub vmap: Box<UserPageTable>,
     /// The scheduling state of the process.
     pub state: State,
+    /// The files opened by the process.
+    pub files: FdTable,
+    /// The ID of the process that forked this one, while it is running.
+    pub parent: Option<Id>,
+    /// The IDs of the children of this process that have not been reaped.
+    pub children: Vec<Id>,
+    /// The IDs of the children of this process that have exited but not been
+    /// reaped, along with their `State::Zombie` state.
+    pub zombies: Vec<(Id, State)>,
+    /// The program the process runs, whose pages are loaded on demand.
+    pub image: Image,
+    /// The start of the heap, the page following the loaded program.
+    pub heap_start: VirtualAddr,
+    /// The end of the heap, the program break.
+    pub brk: VirtualAddr,
+    /// The regions mapped with `mmap()`.
+    pub mappings: Vec<Mapping>,
 }
 
 impl Process {
@@ -61,7 +94,7 @@ impl Process {
     /// Load a program stored in the given path by calling `do_load()` method.
     /// Set trapframe `context` corresponding to the its page table.
     /// `sp` - the address of stack top
-    /// `elr` - the address of image base.
+    /// `elr` - the entry point of the program.
     /// `ttbr0` - the base address of kernel page table
     /// `ttbr1` - the base address of user page table
     /// `spsr` - `F`, `A`, `D` bit should be set.
@@ -71,51 +104,257 @@ impl Process {
         use crate::VMM;
 
         let mut p = Process::do_load(pn)?;
+        p.files = FdTable::with_stdio()?;
+        let entry = p.context.ELR;
+        p.init_context(entry);
+
+        Ok(p)
+    }
 
-        //FIXME: Set trapframe for the process.
-        let mut tf = &mut p.context;
-        tf.ELR = Self::get_image_base().as_u64();
+    /// Resets the trapframe `context` to start the loaded program at `entry` as
+    /// described in `load()`. The process ID saved in the trapframe is kept.
+    fn init_context(&mut self, entry: u64) {
+        let id = self.context.TPIDR;
+        let tf = &mut self.context;
+        **tf = TrapFrame::default();
+        tf.ELR = entry;
         tf.SPSR = (SPSR_EL1::M & 0b0000) | SPSR_EL1::F | SPSR_EL1::A | SPSR_EL1::D;
         tf.SP = Self::get_stack_top().as_u64();
+        tf.TPIDR = id;
         tf.TTBR0 = crate::VMM.get_baddr().as_u64();
-        tf.TTBR1 = p.vmap.get_baddr().as_u64();
+        tf.TTBR1 = self.vmap.get_baddr().as_u64();
+    }
 
-        Ok(p)
+    /// Replaces the program of this process with the one stored at `pn`,
+    /// started with the arguments `args`. The process ID and the open files are
+    /// kept, while the pages of the current program are freed.
+    ///
+    /// The trapframe `context` is reset as described in `load()`, with the
+    /// arguments laid out at the top of the stack: `x0` holds the number of
+    /// arguments and `x1` the address of an array of `(address, length)` pairs,
+    /// the layout of a `&[&str]`.
+    ///
+    /// On failure, the process is left untouched. Returns `OsError::NoEntry` if
+    /// there is no file at `pn`, and `OsError::InvalidArgument` if the
+    /// arguments do not fit in half of the stack page.
+    pub fn exec<P: AsRef<Path>>(&mut self, pn: P, args: &[&[u8]]) -> OsResult<()> {
+        let strings_size = align_up(args.iter().map(|arg| arg.len()).sum(), 16);
+        let table_size = args.len() * 16;
+        if strings_size + table_size > PAGE_SIZE / 2 {
+            return Err(OsError::InvalidArgument);
+        }
+
+        let mut loaded = Process::do_load(pn)?;
+        let strings_addr = Self::get_stack_top().as_usize() - strings_size;
+        let table_addr = strings_addr - table_size;
+        let mut table: Vec<u8> = Vec::with_capacity(table_size);
+        let mut addr = strings_addr;
+        for arg in args {
+            loaded.vmap.copy_to(VirtualAddr::from(addr), arg)?;
+            table.extend_from_slice(&(addr as u64).to_le_bytes());
+            table.extend_from_slice(&(arg.len() as u64).to_le_bytes());
+            addr += arg.len();
+        }
+        loaded.vmap.copy_to(VirtualAddr::from(table_addr), &table)?;
+
+        let entry = loaded.context.ELR;
+        self.vmap = loaded.vmap;
+        self.image = loaded.image;
+        self.heap_start = loaded.heap_start;
+        self.brk = loaded.brk;
+        self.mappings = loaded.mappings;
+        self.init_context(entry);
+        self.context.SP = table_addr as u64;
+        self.context.x[0] = args.len() as u64;
+        self.context.x[1] = table_addr as u64;
+        Ok(())
     }
 
     /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
+    /// Allocates one page for stack with read/write permission, the top one of
+    /// the stack that is grown by `handle_translation_fault()`, and reads the
+    /// program's segments. The trapframe's `ELR` is set to the entry point of
+    /// the program.
+    ///
+    /// No page of the program is loaded yet: each is read from the file by
+    /// `handle_translation_fault()` when it is first accessed. A file starting
+    /// with the ELF magic number is an ELF64 executable, as described in
+    /// `read_elf()`. Any other file is a flat binary loaded at
+    /// `USER_IMG_BASE`, its entry point.
     fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
         let mut vmap = Box::new(UserPageTable::new());
         let mut stack = vmap.alloc(Self::get_stack_base(), PagePerm::RW);
         for byte in stack.iter_mut() {
             *byte = 0;
         }
-        let entry = (&crate::FILESYSTEM).open(pn)?;
-        let mut file = entry.into_file().ok_or(OsError::NoEntry)?;
-        let mut image_addr = Self::get_image_base();
-        let file_size = file.size() as usize;
-        // kprintln!("file size: {}", file_size);
-        for i in 0..file_size / PAGE_SIZE {
-            // kprintln!("loading page {}", i);
-            let mut page = vmap.alloc(image_addr, PagePerm::RWX);
-            file.read_exact(&mut page)?;
-            image_addr += VirtualAddr::from(PAGE_SIZE);
-        }
-        if file_size % PAGE_SIZE != 0 {
-            // kprintln!("loading last page");
-            let mut page = vmap.alloc(image_addr, PagePerm::RWX);
-            file.read_exact(&mut page[..file_size % PAGE_SIZE])?;
-        }
+        let mut file = (&crate::VFS)
+            .open(pn)?
+            .into_file()
+            .ok_or(OsError::NoEntry)?;
+
+        let mut header = [0u8; mem::size_of::<ElfHeader>()];
+        let is_elf = file.size() >= header.len() as u64 && {
+            file.read_exact(&mut header)?;
+            file.seek(SeekFrom::Start(0))?;
+            ElfHeader::is_elf(&header)
+        };
+        let (entry, segments) = if is_elf {
+            Self::read_elf(&mut file, &header)?
+        } else {
+            Self::read_flat(&file)
+        };
+        let image = Image::new(OpenFile::new(file), segments);
+        let image_end = image.end();
+
+        let mut context = Box::new(TrapFrame::default());
+        context.ELR = entry.as_u64();
         Ok(Self {
-            context: Box::new(TrapFrame::default()),
+            context,
             // stack: Unique::new(stack as *mut _).expect("non-null"),
             vmap: vmap,
             state: State::Ready,
+            files: FdTable::new(),
+            parent: None,
+            children: Vec::new(),
+            zombies: Vec::new(),
+            image,
+            heap_start: image_end,
+            brk: image_end,
+            mappings: Vec::new(),
         })
     }
 
+    /// Returns the entry point of the flat binary `file`, the image base, and
+    /// a single segment mapping the whole file there. Nothing tells its text
+    /// and data apart, so its pages are read/write/execute.
+    fn read_flat<F: File>(file: &F) -> (VirtualAddr, Vec<ProgramHeader>) {
+        let size = file.size();
+        let segment = ProgramHeader {
+            kind: elf::PT_LOAD,
+            flags: elf::PF_W | elf::PF_X,
+            offset: 0,
+            vaddr: USER_IMG_BASE as u64,
+            paddr: USER_IMG_BASE as u64,
+            filesz: size,
+            memsz: size,
+            align: PAGE_SIZE as u64,
+        };
+        (Self::get_image_base(), alloc::vec![segment])
+    }
+
+    /// Reads the `PT_LOAD` segments of the ELF64 executable `file`, whose
+    /// first bytes are `header`, and returns its entry point and the segments.
+    ///
+    /// Each segment is later loaded at its virtual address from its file
+    /// contents by `Image::load_page()`, with the rest of its memory size
+    /// zeroed. Its pages are mapped with the permissions of its flags: text is
+    /// read-only and executable, data is read/write. Segments may share a page.
+    ///
+    /// Returns `OsError::IoErrorInvalidData` if the file is not an AArch64
+    /// executable or if a segment or the entry point lies outside of the user
+    /// address space below the stack and its guard region, or if the program
+    /// headers or a segment extend past the end of the file.
+    fn read_elf<F: File>(
+        file: &mut F,
+        header: &[u8],
+    ) -> OsResult<(VirtualAddr, Vec<ProgramHeader>)> {
+        let header = ElfHeader::from(header)?;
+        let in_image = |start: u64, len: u64| {
+            start >= USER_IMG_BASE as u64
+                && start
+                    .checked_add(len)
+                    .map_or(false, |end| end <= Self::get_stack_guard().as_u64())
+        };
+        if !in_image(header.entry, 1) {
+            return Err(OsError::IoErrorInvalidData);
+        }
+
+        let file_size = file.size();
+        let entry_size = header.phentsize as usize;
+        let headers_size = entry_size
+            .checked_mul(header.phnum as usize)
+            .ok_or(OsError::IoErrorInvalidData)?;
+        let in_file = header
+            .phoff
+            .checked_add(headers_size as u64)
+            .map_or(false, |end| end <= file_size);
+        if !in_file {
+            return Err(OsError::IoErrorInvalidData);
+        }
+        let mut headers = alloc::vec![0u8; headers_size];
+        file.seek(SeekFrom::Start(header.phoff))?;
+        file.read_exact(&mut headers)?;
+        let segments: Vec<ProgramHeader> = headers
+            .chunks(entry_size)
+            .map(ProgramHeader::from)
+            .filter(|segment| segment.is_load())
+            .collect();
+        for segment in &segments {
+            if segment.filesz > segment.memsz
+                || !in_image(segment.vaddr, segment.memsz)
+                || segment
+                    .offset
+                    .checked_add(segment.filesz)
+                    .map_or(true, |end| end > file_size)
+            {
+                return Err(OsError::IoErrorInvalidData);
+            }
+        }
+        Ok((VirtualAddr::from(header.entry), segments))
+    }
+
+    /// Returns a copy of this process to be scheduled as its child. The child
+    /// resumes from the trap frame `tf` of this process with a zero return
+    /// value. Both processes share the pages of this process until one of
+    /// them writes to a page, which then gets copied: see
+    /// `UserPageTable::share()`. Open files are shared with this process.
+    ///
+    /// Returns `OsError::NoMemory` if the child's page table cannot be
+    /// allocated.
+    pub fn fork(&mut self, tf: &TrapFrame) -> OsResult<Process> {
+        let vmap = Box::new(self.vmap.share()?);
+        let mut context = Box::new(*tf);
+        context.TTBR1 = vmap.get_baddr().as_u64();
+        context.x[0] = 0;
+        context.x[7] = OsError::Ok as u64;
+        Ok(Process {
+            context,
+            vmap,
+            state: State::Ready,
+            files: self.files.clone(),
+            parent: Some(tf.TPIDR),
+            children: Vec::new(),
+            zombies: Vec::new(),
+            image: self.image.clone(),
+            heap_start: self.heap_start,
+            brk: self.brk,
+            mappings: self.mappings.clone(),
+        })
+    }
+
+    /// Returns `true` if the process `pid` is a child of this process that has
+    /// not been reaped yet. If `pid` is `WAIT_ANY`, returns `true` if there is
+    /// any such child.
+    pub fn has_child(&self, pid: Id) -> bool {
+        self.children.iter().any(|&id| pid == WAIT_ANY || id == pid)
+    }
+
+    /// Reaps the child `pid` of this process if it has exited, or any exited
+    /// child if `pid` is `WAIT_ANY`. Returns the ID and exit status of the
+    /// reaped child, or `None` if no matching child has exited.
+    pub fn reap(&mut self, pid: Id) -> Option<(Id, i32)> {
+        let index = self
+            .zombies
+            .iter()
+            .position(|&(id, _)| pid == WAIT_ANY || id == pid)?;
+        let (id, state) = self.zombies.remove(index);
+        self.children.retain(|&child| child != id);
+        match state {
+            State::Zombie(status) => Some((id, status)),
+            _ => unreachable!("reaped process is not a zombie"),
+        }
+    }
+
     /// Returns the highest `VirtualAddr` that is supported by this system.
     pub fn get_max_va() -> VirtualAddr {
         VirtualAddr::from(USER_MAX_VA)
@@ -139,6 +378,149 @@ impl Process {
         VirtualAddr::from(align_down(USER_MAX_VA, 16))
     }
 
+    /// Returns the `VirtualAddr` represents the lowest address the user
+    /// process's stack can grow down to, `USER_STACK_MAX_SIZE` below its top.
+    pub fn get_stack_limit() -> VirtualAddr {
+        Self::get_stack_base() - VirtualAddr::from(USER_STACK_MAX_SIZE - PAGE_SIZE)
+    }
+
+    /// Returns the `VirtualAddr` represents the base address of the guard
+    /// region below the stack limit, which is never mapped.
+    pub fn get_stack_guard() -> VirtualAddr {
+        Self::get_stack_limit() - VirtualAddr::from(USER_STACK_GUARD_SIZE)
+    }
+
+    /// Moves the program break, the end of the heap, to `brk` and returns it.
+    /// If `brk` is zero, the break is returned without being moved.
+    ///
+    /// The heap starts at `heap_start`, after the loaded program, and may grow
+    /// up to the guard region below the stack. Pages it grows into are mapped
+    /// read/write and zeroed. Pages it no longer reaches are freed.
+    ///
+    /// Returns `OsError::NoVmSpace` if `brk` is below the start of the heap,
+    /// beyond its limit, or if the heap would grow into mapped pages, and
+    /// `OsError::NoMemory` if its pages cannot be allocated. The break is left
+    /// unchanged on error.
+    pub fn set_brk(&mut self, brk: VirtualAddr) -> OsResult<VirtualAddr> {
+        if brk.as_usize() == 0 {
+            return Ok(self.brk);
+        }
+        if brk.as_usize() < self.heap_start.as_usize()
+            || brk.as_usize() > Self::get_stack_guard().as_usize()
+        {
+            return Err(OsError::NoVmSpace);
+        }
+
+        let old_end = align_up(self.brk.as_usize(), PAGE_SIZE);
+        let new_end = align_up(brk.as_usize(), PAGE_SIZE);
+        if new_end > old_end && !self.is_unmapped(old_end, new_end) {
+            return Err(OsError::NoVmSpace);
+        }
+        for page in (old_end..new_end).step_by(PAGE_SIZE) {
+            match self.vmap.try_alloc(VirtualAddr::from(page), PagePerm::RW) {
+                Ok(buf) => {
+                    for byte in buf.iter_mut() {
+                        *byte = 0;
+                    }
+                }
+                Err(e) => {
+                    for page in (old_end..page).step_by(PAGE_SIZE) {
+                        self.vmap.dealloc(VirtualAddr::from(page));
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        for page in (new_end..old_end).step_by(PAGE_SIZE) {
+            self.vmap.dealloc(VirtualAddr::from(page));
+        }
+        self.brk = brk;
+        Ok(brk)
+    }
+
+    /// Returns `true` if no page between the page aligned user addresses
+    /// `start` and `end` is mapped.
+    fn is_unmapped(&self, start: usize, end: usize) -> bool {
+        (start..end)
+            .step_by(PAGE_SIZE)
+            .all(|page| !self.vmap.is_mapped(VirtualAddr::from(page), PAGE_SIZE))
+    }
+
+    /// Handles a translation fault of this process accessing the unmapped user
+    /// address `va`.
+    ///
+    /// An address in a page of the program image is loaded from the program's
+    /// file and `FaultAction::Retry` is returned, as for an address between
+    /// the stack limit and the top of the stack, in the part of the stack that
+    /// has not been used yet: its page is mapped and zeroed. An address in the
+    /// guard region below returns `FaultAction::StackOverflow`. Any other
+    /// address, or a failure to read the image, returns
+    /// `FaultAction::Invalid`, and a failure to allocate the page returns
+    /// `FaultAction::OutOfMemory`.
+    pub fn handle_translation_fault(&mut self, va: VirtualAddr) -> FaultAction {
+        let page = VirtualAddr::from(align_down(va.as_usize(), PAGE_SIZE));
+        if self.vmap.is_mapped(page, PAGE_SIZE) {
+            return FaultAction::Invalid;
+        }
+        if va.as_usize() < self.heap_start.as_usize() {
+            return match self.image.load_page(&mut self.vmap, page) {
+                Ok(true) => FaultAction::Retry,
+                Err(OsError::NoMemory) => FaultAction::OutOfMemory,
+                Ok(false) | Err(_) => FaultAction::Invalid,
+            };
+        }
+        if va.as_usize() >= Self::get_stack_limit().as_usize() {
+            match self.vmap.try_alloc(page, PagePerm::RW) {
+                Ok(bytes) => {
+                    for byte in bytes.iter_mut() {
+                        *byte = 0;
+                    }
+                    FaultAction::Retry
+                }
+                Err(_) => FaultAction::OutOfMemory,
+            }
+        } else if va.as_usize() >= Self::get_stack_guard().as_usize() {
+            FaultAction::StackOverflow
+        } else {
+            FaultAction::Invalid
+        }
+    }
+
+    /// Maps the pages spanned by the `len` bytes at the user address `va` that
+    /// would be mapped by `handle_translation_fault()` on first access, so the
+    /// kernel can access them on behalf of this process. Stops at the first
+    /// page that cannot be mapped.
+    pub fn populate(&mut self, va: VirtualAddr, len: usize) {
+        let start = va.as_usize();
+        let end = match start.checked_add(len) {
+            Some(end) if start >= USER_IMG_BASE && len <= USER_MAX_VM_SIZE => end,
+            _ => return,
+        };
+        for page in (align_down(start, PAGE_SIZE)..end).step_by(PAGE_SIZE) {
+            let page = VirtualAddr::from(page);
+            if !self.vmap.is_mapped(page, PAGE_SIZE)
+                && self.handle_translation_fault(page) != FaultAction::Retry
+            {
+                return;
+            }
+        }
+    }
+
+    /// Handles a permission fault of this process writing to the read-only
+    /// user address `va`.
+    ///
+    /// If `va` is in a copy-on-write page, the page is made writable for this
+    /// process and `FaultAction::Retry` is returned, or
+    /// `FaultAction::OutOfMemory` if the page could not be copied. Otherwise,
+    /// `FaultAction::Invalid` is returned.
+    pub fn handle_permission_fault(&mut self, va: VirtualAddr) -> FaultAction {
+        match self.vmap.unshare(va) {
+            Ok(true) => FaultAction::Retry,
+            Ok(false) => FaultAction::Invalid,
+            Err(_) => FaultAction::OutOfMemory,
+        }
+    }
+
     /// Returns `true` if this process is ready to be scheduled.
     ///
     /// This functions returns `true` only if one of the following holds:
@@ -63,11 +63,31 @@ impl GlobalScheduler {
         }
     }
 
-    /// Kills currently running process and returns that process's ID.
-    /// For more details, see the documentaion on `Scheduler::kill()`.
+    /// Enters a critical region and executes the provided closure with the
+    /// process whose trap frame is `tf`, usually the one running a system
+    /// call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no scheduled process has the ID saved in `tf`.
+    pub fn with_process<F, R>(&self, tf: &TrapFrame, f: F) -> R
+    where
+        F: FnOnce(&mut Process) -> R,
+    {
+        self.critical(|scheduler| {
+            let process = scheduler
+                .find_process(tf)
+                .expect("no process for trap frame");
+            f(process)
+        })
+    }
+
+    /// Kills currently running process with exit status `status` and returns
+    /// that process's ID. For more details, see the documentaion on
+    /// `Scheduler::kill()`.
     #[must_use]
-    pub fn kill(&self, tf: &mut TrapFrame) -> Option<Id> {
-        self.critical(|scheduler| scheduler.kill(tf))
+    pub fn kill(&self, tf: &mut TrapFrame, status: i32) -> Option<Id> {
+        self.critical(|scheduler| scheduler.kill(tf, status))
     }
 
     /// Starts executing processes in user space using timer interrupt based
@@ -143,7 +163,14 @@ impl GlobalScheduler {
         //     let p = Process::load("/sleep.bin").expect("load /sleep.bin");
         //     scheduler.add(p);
         // }
-        let p = Process::load("/fib.bin").expect("load /sleep.bin");
+        // `/forktest` checks that the kernel heap does not grow, so it runs
+        // alone: other processes would allocate while it measures.
+        let program = if cfg!(feature = "forktest") {
+            "/forktest"
+        } else {
+            "/fib"
+        };
+        let p = Process::load(program).expect("load user program");
         scheduler.add(p);
         *self.0.lock() = Some(scheduler);
     }
@@ -198,10 +225,26 @@ impl Scheduler {
         };
         self.last_id = Some(id);
         process.context.TPIDR = id;
+        if let Some(parent) = process.parent.and_then(|parent| self.get_process(parent)) {
+            parent.children.push(id);
+        }
         self.processes.push_back(process);
         Some(id)
     }
 
+    /// Returns the process whose ID is the one saved in `tf`, if it is
+    /// scheduled.
+    fn find_process(&mut self, tf: &TrapFrame) -> Option<&mut Process> {
+        self.get_process(tf.TPIDR)
+    }
+
+    /// Returns the process with ID `id`, if it is scheduled.
+    fn get_process(&mut self, id: Id) -> Option<&mut Process> {
+        self.processes
+            .iter_mut()
+            .find(|process| process.context.TPIDR == id)
+    }
+
     /// Finds the currently running process, sets the current process's state
     /// to `new_state`, prepares the context switch on `tf` by saving `tf`
     /// into the current process, and push the current process back to the
@@ -255,18 +298,34 @@ impl Scheduler {
     }
 
     /// Kills currently running process by scheduling out the current process
-    /// as `Dead` state. Removes the dead process from the queue, drop the
-    /// dead process's instance, and returns the dead process's process ID.
-    fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        if let Some(mut process) = self.processes.pop_front() {
-            if let State::Running = process.state {
-                process.state = State::Dead;
-                return Some(process.context.TPIDR);
-            } else {
+    /// as `Dead` state with exit status `status`, and returns the dead
+    /// process's process ID.
+    ///
+    /// The dead process is removed from the queue and its instance is
+    /// dropped, closing its files and freeing its memory. Its ID and its
+    /// `Zombie` state holding the exit status are handed to its parent, which
+    /// reaps it with `Process::reap()`. Its children lose their parent.
+    fn kill(&mut self, tf: &mut TrapFrame, status: i32) -> Option<Id> {
+        let mut process = self.processes.pop_front()?;
+        match process.state {
+            State::Running => {}
+            _ => {
                 self.processes.push_front(process);
+                return None;
             }
         }
-        None
+
+        let id = process.context.TPIDR;
+        process.state = State::Dead;
+        for child in self.processes.iter_mut() {
+            if child.parent == Some(id) {
+                child.parent = None;
+            }
+        }
+        if let Some(parent) = process.parent.and_then(|parent| self.get_process(parent)) {
+            parent.zombies.push((id, State::Zombie(status)));
+        }
+        Some(id)
     }
 }
 
@@ -291,12 +350,12 @@ pub extern "C" fn test_user_process() -> ! {
 
 pub extern "C" fn start_shell1() {
     loop {
-        shell::shell("user1> ", &crate::FILESYSTEM);
+        shell::shell("user1> ", &crate::VFS);
     }
 }
 
 pub extern "C" fn start_shell2() {
     loop {
-        shell::shell("user2> ", &crate::FILESYSTEM);
+        shell::shell("user2> ", &crate::VFS);
     }
 }
@@ -21,6 +21,10 @@ pub enum State {
     Running,
     /// The process is currently dead (ready to be reclaimed).
     Dead,
+    /// The process has exited with the given status, which is kept until its
+    /// parent collects it with `wait`. The memory of the process is freed
+    /// when it exits: only its parent keeps its ID along with this state.
+    Zombie(i32),
 }
 
 impl fmt::Debug for State {
@@ -30,6 +34,7 @@ impl fmt::Debug for State {
             State::Running => write!(f, "State::Running"),
             State::Waiting(_) => write!(f, "State::Waiting"),
             State::Dead => write!(f, "State::Dead"),
+            State::Zombie(status) => write!(f, "State::Zombie({})", status),
         }
     }
 }
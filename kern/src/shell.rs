@@ -16,7 +16,7 @@ use fat32::traits::{Dir, Entry};
 
 use crate::console::{kprint, kprintln, CONSOLE};
 use crate::ALLOCATOR;
-// use crate::FILESYSTEM;
+// use crate::VFS;
 
 // /// Error type for `Command` parse failures.
 // #[derive(Debug)]
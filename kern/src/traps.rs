@@ -6,13 +6,18 @@ pub mod irq;
 pub use self::frame::TrapFrame;
 
 use crate::console::{kprint, kprintln};
+use crate::process::FaultAction;
 use crate::shell;
+use crate::vm::VirtualAddr;
 use crate::IRQ;
+use crate::SCHEDULER;
 
+use aarch64::FAR_EL1;
 use fat32;
+use kernel_api::EXIT_KILLED;
 use pi::interrupt::{Controller, Interrupt};
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 
 #[repr(u16)]
@@ -56,7 +61,7 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
     // kprintln!("info: {:?}, esr: {:?}", info, esr);
     // kprintln!("tf: {:#?}", tf);
     // kprintln!("exception at 0x{:06x}", tf.ELR);
-    // shell::shell("! ", &crate::FILESYSTEM);
+    // shell::shell("! ", &crate::VFS);
 
     match info.kind {
         Kind::Synchronous => {
@@ -69,8 +74,31 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 Svc(num) => {
                     handle_syscall(num, tf);
                 }
+                DataAbort {
+                    kind: Fault::Translation,
+                    ..
+                }
+                | InstructionAbort {
+                    kind: Fault::Translation,
+                    ..
+                } if info.source == Source::LowerAArch64 => {
+                    handle_translation_fault(syndrome, tf);
+                }
+                DataAbort {
+                    kind: Fault::Permission,
+                    ..
+                } if info.source == Source::LowerAArch64 => {
+                    handle_permission_fault(syndrome, tf);
+                }
+                _ if info.source == Source::LowerAArch64 => {
+                    kill_faulting(syndrome, tf);
+                }
                 _ => {
-                    panic!("Unexpected syndrome {:?}", syndrome);
+                    let far = unsafe { FAR_EL1.get() };
+                    panic!(
+                        "Unexpected syndrome {:?} at 0x{:x}, address 0x{:x}",
+                        syndrome, tf.ELR, far
+                    );
                 }
             }
         }
@@ -86,3 +114,96 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
         _ => {}
     }
 }
+
+/// Handles a translation fault of the user process whose trap frame is `tf`
+/// at the address in `FAR_EL1`, loading the page of its program image or
+/// growing its stack if the address is in the stack's unused part. The
+/// faulting access is retried once the page is mapped. Otherwise, the process
+/// is killed.
+fn handle_translation_fault(syndrome: Syndrome, tf: &mut TrapFrame) {
+    let far = unsafe { FAR_EL1.get() };
+    let action = SCHEDULER.with_process(tf, |process| {
+        process.handle_translation_fault(VirtualAddr::from(far))
+    });
+    match action {
+        FaultAction::Retry => {}
+        FaultAction::StackOverflow => {
+            kprintln!(
+                "process {} killed: stack overflow at 0x{:x}, address 0x{:x}",
+                tf.TPIDR,
+                tf.ELR,
+                far
+            );
+            kill_current(tf);
+        }
+        FaultAction::Invalid => kill_faulting(syndrome, tf),
+        FaultAction::OutOfMemory => kill_out_of_memory(far, tf),
+    }
+}
+
+/// Handles a permission fault of the user process whose trap frame is `tf`
+/// at the address in `FAR_EL1`. A write to a copy-on-write page is retried
+/// once the process has its own writable copy of the page. Otherwise, or if
+/// the page cannot be copied, the process is killed.
+fn handle_permission_fault(syndrome: Syndrome, tf: &mut TrapFrame) {
+    let far = unsafe { FAR_EL1.get() };
+    let action = SCHEDULER.with_process(tf, |process| {
+        process.handle_permission_fault(VirtualAddr::from(far))
+    });
+    match action {
+        FaultAction::Retry => {}
+        FaultAction::OutOfMemory => kill_out_of_memory(far, tf),
+        _ => kill_faulting(syndrome, tf),
+    }
+}
+
+/// Kills the user process whose trap frame is `tf` because the page at the
+/// address `far` it faulted on could not be allocated, and switches to the
+/// next process.
+fn kill_out_of_memory(far: u64, tf: &mut TrapFrame) {
+    kprintln!(
+        "process {} killed: out of memory at 0x{:x}, address 0x{:x}",
+        tf.TPIDR,
+        tf.ELR,
+        far
+    );
+    kill_current(tf);
+}
+
+/// Kills the user process whose trap frame is `tf` after an exception
+/// described by `syndrome` it cannot recover from, such as an access to an
+/// unmapped address, and switches to the next process. A diagnostic line names
+/// the process, the faulting instruction and, for aborts, the faulting address
+/// from `FAR_EL1`.
+fn kill_faulting(syndrome: Syndrome, tf: &mut TrapFrame) {
+    use Syndrome::*;
+
+    match syndrome {
+        InstructionAbort { .. } | DataAbort { .. } => {
+            let far = unsafe { FAR_EL1.get() };
+            kprintln!(
+                "process {} killed: {:?} at 0x{:x}, address 0x{:x}",
+                tf.TPIDR,
+                syndrome,
+                tf.ELR,
+                far
+            );
+        }
+        _ => {
+            kprintln!(
+                "process {} killed: {:?} at 0x{:x}",
+                tf.TPIDR,
+                syndrome,
+                tf.ELR
+            );
+        }
+    }
+    kill_current(tf);
+}
+
+/// Kills the user process whose trap frame is `tf` with the exit status
+/// `EXIT_KILLED` and switches to the next process.
+fn kill_current(tf: &mut TrapFrame) {
+    let _ = SCHEDULER.kill(tf, EXIT_KILLED);
+    SCHEDULER.switch_to(tf);
+}
@@ -1,13 +1,89 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::time::Duration;
 
+use fat32::traits::{Entry, File, FileSystem};
+use shim::io::{self, Read, Seek, SeekFrom, Write};
+
 use crate::console::CONSOLE;
-use crate::process::State;
+use crate::process::{OpenFile, State};
 use crate::traps::TrapFrame;
+use crate::vm::VirtualAddr;
 use crate::SCHEDULER;
+use crate::VFS;
 use kernel_api::*;
 use pi::timer::current_time;
 
+/// Stores the outcome of a system call in `tf`: the returned value in `x0` on
+/// success, and the status value in `x7`.
+fn set_result(tf: &mut TrapFrame, result: OsResult<u64>) {
+    match result {
+        Ok(value) => {
+            tf.x[0] = value;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => tf.x[7] = e as u64,
+    }
+}
+
+/// Fails with `OsError::BadAddress` unless the `len` bytes at the user address
+/// `va` are mapped in the page table of the process whose trap frame is `tf`.
+/// Pages of the range that are mapped on demand are mapped first.
+fn check_user_range(tf: &TrapFrame, va: u64, len: u64) -> OsResult<()> {
+    let mapped = SCHEDULER.with_process(tf, |process| {
+        let va = VirtualAddr::from(va as usize);
+        process.populate(va, len as usize);
+        process.vmap.is_mapped(va, len as usize)
+    });
+    if mapped {
+        Ok(())
+    } else {
+        Err(OsError::BadAddress)
+    }
+}
+
+/// Fails with `OsError::BadAddress` unless the `len` bytes at the user address
+/// `va` are mapped in the page table of the process whose trap frame is `tf`,
+/// and writable by that process. Pages of the range that are mapped on demand
+/// are mapped first, and copy-on-write pages are unshared so the kernel can
+/// write to them: fails with `OsError::NoMemory` if a page cannot be copied.
+fn check_user_writable(tf: &TrapFrame, va: u64, len: u64) -> OsResult<()> {
+    SCHEDULER.with_process(tf, |process| {
+        let va = VirtualAddr::from(va as usize);
+        process.populate(va, len as usize);
+        process.vmap.make_writable(va, len as usize)
+    })
+}
+
+/// Returns the `len` bytes at the user address `va` of the process whose
+/// trap frame is `tf`. The process's page table is the active user page table
+/// during its system calls, so the slice can be used until the call returns.
+///
+/// An empty slice is returned without checking `va`, which is then often the
+/// dangling address of an empty user slice.
+fn user_slice<'a>(tf: &TrapFrame, va: u64, len: u64) -> OsResult<&'a [u8]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    check_user_range(tf, va, len)?;
+    Ok(unsafe { core::slice::from_raw_parts(va as *const u8, len as usize) })
+}
+
+/// Mutable counterpart of `user_slice()`, for writable user memory.
+fn user_slice_mut<'a>(tf: &TrapFrame, va: u64, len: u64) -> OsResult<&'a mut [u8]> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    check_user_writable(tf, va, len)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(va as *mut u8, len as usize) })
+}
+
+/// Returns the file open at descriptor `fd` in the process whose trap frame is
+/// `tf`.
+fn open_file(tf: &TrapFrame, fd: u64) -> OsResult<OpenFile> {
+    SCHEDULER.with_process(tf, |process| process.files.get(fd))
+}
+
 /// Sleep for `ms` milliseconds.
 ///
 /// This system call takes one parameter: the number of milliseconds to sleep.
@@ -50,9 +126,10 @@ pub fn sys_time(tf: &mut TrapFrame) {
 
 /// Kills current process.
 ///
-/// This system call does not take paramer and does not return any value.
-pub fn sys_exit(tf: &mut TrapFrame) {
-    SCHEDULER.kill(tf);
+/// This system call takes one parameter: the exit status, kept for the
+/// process's parent to collect with `wait`. It does not return any value.
+pub fn sys_exit(status: i32, tf: &mut TrapFrame) {
+    SCHEDULER.kill(tf, status);
     SCHEDULER.switch_to(tf);
 }
 
@@ -61,9 +138,7 @@ pub fn sys_exit(tf: &mut TrapFrame) {
 /// This system call takes one parameter: a u8 character to print.
 ///
 /// It only returns the usual status value.
-pub fn sys_write(b: u8, tf: &mut TrapFrame) {
-    use shim::io::Write;
-
+pub fn sys_write_byte(b: u8, tf: &mut TrapFrame) {
     (&crate::console::CONSOLE).write(&[b]);
     tf.x[7] = 1;
 }
@@ -79,6 +154,301 @@ pub fn sys_getpid(tf: &mut TrapFrame) {
     tf.x[7] = 1;
 }
 
+/// Opens a file.
+///
+/// This system call takes three parameters: the address and length of an
+/// absolute UTF-8 path, and the `open` flags. With `O_CREAT`, a missing file
+/// is created, unless it is on a read-only mount such as the SD card, which
+/// fails with `NoAccess`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the file descriptor of the open file, the lowest free one.
+/// Directories cannot be opened and fail with `InvalidArgument`.
+pub fn sys_open(va: u64, len: u64, flags: u64, tf: &mut TrapFrame) {
+    let result = (|| {
+        let path = user_slice(tf, va, len)?;
+        let path = core::str::from_utf8(path).map_err(|_| OsError::InvalidArgument)?;
+        let file = match (&VFS).open(path) {
+            Ok(entry) => entry.into_file().ok_or(OsError::InvalidArgument)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && flags & O_CREAT != 0 => {
+                (&VFS).create_file(path)?
+            }
+            Err(e) => return Err(OsError::from(e)),
+        };
+        SCHEDULER.with_process(tf, |process| process.files.insert(OpenFile::new(file)))
+    })();
+    set_result(tf, result);
+}
+
+/// Reads from a file.
+///
+/// This system call takes three parameters: a file descriptor, and the
+/// address and length of the user buffer to read into.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read, zero at the end of the file.
+pub fn sys_read(fd: u64, va: u64, len: u64, tf: &mut TrapFrame) {
+    let result = (|| {
+        let file = open_file(tf, fd)?;
+        let buf = user_slice_mut(tf, va, len)?;
+        Ok(file.with(|file| file.read(buf))? as u64)
+    })();
+    set_result(tf, result);
+}
+
+/// Writes to a file.
+///
+/// This system call takes three parameters: a file descriptor, and the
+/// address and length of the user buffer to write.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written. Files on read-only mounts fail with
+/// `NoAccess`.
+pub fn sys_write(fd: u64, va: u64, len: u64, tf: &mut TrapFrame) {
+    let result = (|| {
+        let file = open_file(tf, fd)?;
+        let buf = user_slice(tf, va, len)?;
+        Ok(file.with(|file| file.write(buf))? as u64)
+    })();
+    set_result(tf, result);
+}
+
+/// Closes a file descriptor.
+///
+/// This system call takes one parameter: the file descriptor to close. The
+/// file is synced to its file system.
+///
+/// It only returns the usual status value.
+pub fn sys_close(fd: u64, tf: &mut TrapFrame) {
+    let result = (|| {
+        let file = SCHEDULER.with_process(tf, |process| process.files.remove(fd))?;
+        file.with(|file| file.sync())?;
+        Ok(0)
+    })();
+    set_result(tf, result);
+}
+
+/// Moves the position of a file descriptor.
+///
+/// This system call takes three parameters: a file descriptor, a signed
+/// offset and its origin, one of `SEEK_SET`, `SEEK_CUR` and `SEEK_END`.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the new position from the start of the file.
+pub fn sys_seek(fd: u64, offset: u64, whence: u64, tf: &mut TrapFrame) {
+    let result = (|| {
+        let pos = match whence {
+            SEEK_SET => SeekFrom::Start(offset),
+            SEEK_CUR => SeekFrom::Current(offset as i64),
+            SEEK_END => SeekFrom::End(offset as i64),
+            _ => return Err(OsError::InvalidArgument),
+        };
+        let file = open_file(tf, fd)?;
+        Ok(file.with(|file| file.seek(pos))?)
+    })();
+    set_result(tf, result);
+}
+
+/// Writes to the console.
+///
+/// This system call takes two parameters: the address and length of the user
+/// buffer to write.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written.
+pub fn sys_console_write(va: u64, len: u64, tf: &mut TrapFrame) {
+    let result = user_slice(tf, va, len).and_then(|buf| Ok((&CONSOLE).write(buf)? as u64));
+    set_result(tf, result);
+}
+
+/// Reads from the console.
+///
+/// This system call takes two parameters: the address and length of the user
+/// buffer to read into. The process waits without being scheduled until the
+/// console has data, then reads the bytes available, up to the buffer length.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read.
+pub fn sys_console_read(va: u64, len: u64, tf: &mut TrapFrame) {
+    if len == 0 {
+        return set_result(tf, Ok(0));
+    }
+    if let Err(e) = check_user_writable(tf, va, len) {
+        return set_result(tf, Err(e));
+    }
+    SCHEDULER.switch(
+        State::Waiting(Box::new(move |p| {
+            let mut console = CONSOLE.lock();
+            if !console.has_byte() {
+                return false;
+            }
+            let mut buf = [0u8; 128];
+            let max = core::cmp::min(len as usize, buf.len());
+            let mut read = 0;
+            while read < max && console.has_byte() {
+                buf[read] = console.read_byte();
+                read += 1;
+            }
+            let result = p
+                .vmap
+                .copy_to(VirtualAddr::from(va), &buf[..read])
+                .map(|()| read as u64);
+            set_result(&mut p.context, result);
+            true
+        })),
+        tf,
+    );
+}
+
+/// Creates a child process.
+///
+/// This system call does not take parameter. The child is a copy of the
+/// current process, with a copy-on-write copy of its memory, its open files,
+/// and a new process ID. Both processes return from the call. Fails with
+/// `NoMemory` if the child cannot be allocated.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the child's process ID in the parent, and 0 in the child.
+pub fn sys_fork(tf: &mut TrapFrame) {
+    let result = SCHEDULER
+        .with_process(tf, |process| process.fork(tf))
+        .and_then(|child| SCHEDULER.add(child).ok_or(OsError::NoMemory));
+    set_result(tf, result);
+}
+
+/// Waits for a child process to exit and reaps it.
+///
+/// This system call takes one parameter: the ID of the child to wait for, or
+/// `WAIT_ANY` for any child. The process waits without being scheduled until a
+/// matching child has exited. Fails with `NoEntry` if the current process has
+/// no such child.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters:
+///  - the ID of the reaped child
+///  - its exit status.
+pub fn sys_wait(pid: u64, tf: &mut TrapFrame) {
+    fn set_reaped(tf: &mut TrapFrame, (id, status): (u64, i32)) {
+        set_result(tf, Ok(id));
+        tf.x[1] = status as u64;
+    }
+
+    let reaped = SCHEDULER.with_process(tf, |p| {
+        if p.has_child(pid) {
+            Ok(p.reap(pid))
+        } else {
+            Err(OsError::NoEntry)
+        }
+    });
+    match reaped {
+        Ok(Some(child)) => set_reaped(tf, child),
+        Ok(None) => {
+            SCHEDULER.switch(
+                State::Waiting(Box::new(move |p| match p.reap(pid) {
+                    Some(child) => {
+                        set_reaped(&mut p.context, child);
+                        true
+                    }
+                    None => false,
+                })),
+                tf,
+            );
+        }
+        Err(e) => set_result(tf, Err(e)),
+    }
+}
+
+/// Moves the program break, the end of the current process's heap.
+///
+/// This system call takes one parameter: the address of the new break, or 0
+/// to only query it. The heap starts at the page following the loaded program.
+/// Fails with `NoVmSpace` if the break cannot be moved there.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the program break.
+pub fn sys_brk(addr: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.with_process(tf, |process| process.set_brk(VirtualAddr::from(addr)));
+    set_result(tf, result.map(|brk| brk.as_u64()));
+}
+
+/// Maps memory in the current process.
+///
+/// This system call takes five parameters: the length of the mapping, its
+/// protection flags (`PROT_*`), the `mmap` flags, and the file descriptor and
+/// page aligned offset of the file to map. With `MAP_ANONYMOUS`, the memory is
+/// zeroed and the last two parameters are ignored. Otherwise, it holds a
+/// read-only copy of the file's contents.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the address of the mapping.
+pub fn sys_mmap(len: u64, prot: u64, flags: u64, fd: u64, offset: u64, tf: &mut TrapFrame) {
+    let result = (|| {
+        if flags & !MAP_ANONYMOUS != 0 {
+            return Err(OsError::InvalidArgument);
+        }
+        let file = if flags & MAP_ANONYMOUS != 0 {
+            None
+        } else {
+            Some(open_file(tf, fd)?)
+        };
+        let addr =
+            SCHEDULER.with_process(tf, |process| process.mmap(len as usize, prot, file, offset))?;
+        Ok(addr.as_u64())
+    })();
+    set_result(tf, result);
+}
+
+/// Unmaps memory mapped with `mmap` in the current process.
+///
+/// This system call takes two parameters: the page aligned address and the
+/// length of the range to unmap. Pages of the range that are not mapped with
+/// `mmap` are left untouched.
+///
+/// It only returns the usual status value.
+pub fn sys_munmap(va: u64, len: u64, tf: &mut TrapFrame) {
+    let result = SCHEDULER.with_process(tf, |process| {
+        process.munmap(VirtualAddr::from(va), len as usize)
+    });
+    set_result(tf, result.map(|_| 0));
+}
+
+/// Replaces the program of the current process.
+///
+/// This system call takes four parameters: the address and length of the
+/// absolute UTF-8 path of the program, and the address and length of an array
+/// of `(address, length)` pairs, one per argument.
+///
+/// On success, the call does not return: the new program starts with the
+/// number of arguments in `x0` and the address of their copy in `x1`. On
+/// failure, only the usual status value is returned.
+pub fn sys_exec(path_va: u64, path_len: u64, argv_va: u64, argc: u64, tf: &mut TrapFrame) {
+    let result = (|| {
+        let path = user_slice(tf, path_va, path_len)?;
+        let path = core::str::from_utf8(path).map_err(|_| OsError::InvalidArgument)?;
+        let argv_len = argc.checked_mul(16).ok_or(OsError::InvalidArgument)?;
+        let mut args = Vec::new();
+        for pair in user_slice(tf, argv_va, argv_len)?.chunks(16) {
+            let mut words = [0u64; 2];
+            for (word, bytes) in words.iter_mut().zip(pair.chunks(8)) {
+                let mut raw = [0u8; 8];
+                raw.copy_from_slice(bytes);
+                *word = u64::from_le_bytes(raw);
+            }
+            args.push(user_slice(tf, words[0], words[1])?);
+        }
+        SCHEDULER.with_process(tf, |process| {
+            process.exec(path, &args)?;
+            Ok(*process.context)
+        })
+    })();
+    match result {
+        Ok(context) => *tf = context,
+        Err(e) => set_result(tf, Err(e)),
+    }
+}
+
+/// Handles the system call `num` of the process whose trap frame is `tf`. A
+/// process making an unknown system call is killed.
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     use crate::console::kprintln;
     match num as usize {
@@ -89,16 +459,61 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
             sys_time(tf);
         }
         NR_EXIT => {
-            sys_exit(tf);
+            sys_exit(tf.x[0] as i32, tf);
         }
-        NR_WRITE => {
-            sys_write(tf.x[0] as u8, tf);
+        NR_WRITE_BYTE => {
+            sys_write_byte(tf.x[0] as u8, tf);
         }
         NR_GETPID => {
             sys_getpid(tf);
         }
+        NR_OPEN => {
+            sys_open(tf.x[0], tf.x[1], tf.x[2], tf);
+        }
+        NR_READ => {
+            sys_read(tf.x[0], tf.x[1], tf.x[2], tf);
+        }
+        NR_WRITE => {
+            sys_write(tf.x[0], tf.x[1], tf.x[2], tf);
+        }
+        NR_CLOSE => {
+            sys_close(tf.x[0], tf);
+        }
+        NR_SEEK => {
+            sys_seek(tf.x[0], tf.x[1], tf.x[2], tf);
+        }
+        NR_CONSOLE_WRITE => {
+            sys_console_write(tf.x[0], tf.x[1], tf);
+        }
+        NR_CONSOLE_READ => {
+            sys_console_read(tf.x[0], tf.x[1], tf);
+        }
+        NR_FORK => {
+            sys_fork(tf);
+        }
+        NR_EXEC => {
+            sys_exec(tf.x[0], tf.x[1], tf.x[2], tf.x[3], tf);
+        }
+        NR_WAIT => {
+            sys_wait(tf.x[0], tf);
+        }
+        NR_BRK => {
+            sys_brk(tf.x[0], tf);
+        }
+        NR_MMAP => {
+            sys_mmap(tf.x[0], tf.x[1], tf.x[2], tf.x[3], tf.x[4], tf);
+        }
+        NR_MUNMAP => {
+            sys_munmap(tf.x[0], tf.x[1], tf);
+        }
         _ => {
-            unimplemented!("syscall {}", num);
+            kprintln!(
+                "process {} killed: unknown syscall {} at 0x{:x}",
+                tf.TPIDR,
+                num,
+                tf.ELR
+            );
+            super::kill_current(tf);
         }
     }
 }
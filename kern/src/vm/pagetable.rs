@@ -3,19 +3,23 @@ use core::ops::{Deref, DerefMut};
 use core::slice::Iter;
 
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::fmt;
 use core::alloc::{GlobalAlloc, Layout};
 
 use crate::allocator;
 use crate::allocator::util::align_up;
 use crate::console::{kprint, kprintln};
+use crate::mutex::Mutex;
 use crate::param::*;
 use crate::vm::{PhysicalAddr, VirtualAddr};
 use crate::ALLOCATOR;
 
 use pi::common::{IO_BASE, IO_BASE_END};
 
+use aarch64::tlb_flush;
 use aarch64::vmsa::*;
+use kernel_api::{OsError, OsResult};
 use shim::const_assert_size;
 
 #[repr(C)]
@@ -66,6 +70,11 @@ impl L3Entry {
         self.0.get_masked(RawL3Entry::VALID) != 0
     }
 
+    /// Returns `true` if the L3Entry is valid and writable from EL0.
+    fn is_user_writable(&self) -> bool {
+        self.is_valid() && self.0.get_value(RawL3Entry::AP) == EntryPerm::USER_RW
+    }
+
     /// Extracts `ADDR` field of the L3Entry and returns as a `PhysicalAddr`
     /// if valid. Otherwise, return `None`.
     fn get_page_addr(&self) -> Option<PhysicalAddr> {
@@ -113,10 +122,34 @@ impl PageTable {
             l2: L2PageTable::new(),
             l3: [L3PageTable::new(), L3PageTable::new()],
         });
+        pt.link_l3(perm);
+        pt
+    }
+
+    /// Same as `new()`, but returns `OsError::NoMemory` instead of panicking if
+    /// the table cannot be allocated.
+    fn try_new(perm: u64) -> OsResult<Box<PageTable>> {
+        let pt = unsafe { ALLOCATOR.alloc(Layout::new::<PageTable>()) } as *mut PageTable;
+        if pt.is_null() {
+            return Err(OsError::NoMemory);
+        }
+        // A zeroed table has no valid entry, like the tables returned by
+        // `L2PageTable::new()` and `L3PageTable::new()`.
+        let mut pt = unsafe {
+            core::ptr::write_bytes(pt, 0, 1);
+            Box::from_raw(pt)
+        };
+        pt.link_l3(perm);
+        Ok(pt)
+    }
+
+    /// Points the first two entries of the L2 table to the L3 tables, with
+    /// the permissions `perm`.
+    fn link_l3(&mut self, perm: u64) {
         for i in 0..2 {
-            let addr = pt.l3[i].as_ptr();
+            let addr = self.l3[i].as_ptr();
             // kprintln!("l3[{}] addr = 0x{:x}", i, addr.as_u64());
-            let mut entry = &mut pt.l2.entries[i];
+            let mut entry = &mut self.l2.entries[i];
             entry.set_masked(addr.as_u64(), RawL2Entry::ADDR);
             entry.set_bit(RawL2Entry::AF);
             entry.set_value(EntrySh::ISh, RawL2Entry::SH);
@@ -125,7 +158,6 @@ impl PageTable {
             entry.set_value(EntryType::Table, RawL2Entry::TYPE);
             entry.set_bit(RawL2Entry::VALID);
         }
-        pt
     }
 
     /// Returns the (L2index, L3index) extracted from the given virtual address.
@@ -164,6 +196,19 @@ impl PageTable {
         !self.is_valid(va)
     }
 
+    /// Returns the physical address of the page the given page aligned virtual
+    /// address translates to, or `None` if its L3entry is invalid.
+    pub fn get_page_addr(&self, va: VirtualAddr) -> Option<PhysicalAddr> {
+        let (l2_index, l3_index) = Self::locate(va);
+        self.l3[l2_index].entries[l3_index].get_page_addr()
+    }
+
+    /// Returns the L3Entry indicated by the given virtual address.
+    fn get_entry(&self, va: VirtualAddr) -> L3Entry {
+        let (l2_index, l3_index) = Self::locate(va);
+        self.l3[l2_index].entries[l3_index]
+    }
+
     /// Set the given RawL3Entry `entry` to the L3Entry indicated by the given virtual
     /// address.
     pub fn set_entry(&mut self, va: VirtualAddr, entry: RawL3Entry) -> &mut Self {
@@ -200,6 +245,8 @@ impl KernPageTable {
     ///
     /// Set L3entry of ARM physical address starting at 0x00000000 for RAM and
     /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals.
+    /// None of them is executable from EL0, and peripherals are not executable
+    /// from EL1 either.
     /// Each L3 entry should have correct value for lower attributes[10:0] as well
     /// as address[47:16]. Refer to the definition of `RawL3Entry` in `vmsa.rs` for
     /// more details.
@@ -213,6 +260,7 @@ impl KernPageTable {
             entry.set_bit(RawL3Entry::AF);
             entry.set_value(EntrySh::ISh, RawL3Entry::SH);
             entry.set_value(EntryPerm::KERN_RW, RawL3Entry::AP);
+            entry.set_bit(RawL3Entry::UXN);
             entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
             entry.set_value(EntryType::Table, RawL3Entry::TYPE);
             entry.set_bit(RawL3Entry::VALID);
@@ -225,6 +273,8 @@ impl KernPageTable {
             entry.set_bit(RawL3Entry::AF);
             entry.set_value(EntrySh::OSh, RawL3Entry::SH);
             entry.set_value(EntryPerm::KERN_RW, RawL3Entry::AP);
+            entry.set_bit(RawL3Entry::UXN);
+            entry.set_bit(RawL3Entry::PXN);
             entry.set_value(EntryAttr::Dev, RawL3Entry::ATTR);
             entry.set_value(EntryType::Table, RawL3Entry::TYPE);
             entry.set_bit(RawL3Entry::VALID);
@@ -241,12 +291,69 @@ impl KernPageTable {
     }
 }
 
+/// Access permissions of a user page, from EL0. User pages are never
+/// executable from EL1.
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PagePerm {
     RW,
     RO,
+    RX,
     RWX,
 }
 
+/// Reference counts of the physical pages mapped by more than one user page
+/// table, keyed by address. Pages mapped by a single table are not counted.
+struct SharedPages(Mutex<Option<BTreeMap<u64, usize>>>);
+
+static SHARED_PAGES: SharedPages = SharedPages(Mutex::new(None));
+
+impl SharedPages {
+    /// Records that one more table maps the page at `addr`.
+    fn share(&self, addr: PhysicalAddr) {
+        let mut pages = self.0.lock();
+        let count = pages
+            .get_or_insert_with(BTreeMap::new)
+            .entry(addr.as_u64())
+            .or_insert(1);
+        *count += 1;
+    }
+
+    /// Returns `true` if more than one table maps the page at `addr`.
+    fn is_shared(&self, addr: PhysicalAddr) -> bool {
+        let mut pages = self.0.lock();
+        pages
+            .get_or_insert_with(BTreeMap::new)
+            .contains_key(&addr.as_u64())
+    }
+
+    /// Records that one table stopped mapping the page at `addr`. Returns
+    /// `true` if that table was the only one mapping it, so the page is now
+    /// unused. Otherwise, `false` is returned.
+    fn release(&self, addr: PhysicalAddr) -> bool {
+        let mut pages = self.0.lock();
+        let pages = pages.get_or_insert_with(BTreeMap::new);
+        match pages.get_mut(&addr.as_u64()) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 1 {
+                    pages.remove(&addr.as_u64());
+                }
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+/// Frees the page at `addr` unless another user page table still maps it.
+fn free_page(addr: PhysicalAddr) {
+    if SHARED_PAGES.release(addr) {
+        unsafe {
+            ALLOCATOR.dealloc(addr.as_u64() as *mut u8, Page::layout());
+        }
+    }
+}
+
 pub struct UserPageTable(Box<PageTable>);
 
 impl UserPageTable {
@@ -257,16 +364,27 @@ impl UserPageTable {
     }
 
     /// Allocates a page and set an L3 entry translates given virtual address to the
-    /// physical address of the allocated page. Returns the allocated page.
+    /// physical address of the allocated page with the permissions `perm`.
+    /// Returns the allocated page.
     ///
     /// # Panics
     /// Panics if the virtual address is lower than `USER_IMG_BASE`.
     /// Panics if the virtual address has already been allocated.
     /// Panics if allocator fails to allocate a page.
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
+        match self.try_alloc(va, perm) {
+            Ok(page) => page,
+            Err(_) => panic!("allocation failed"),
+        }
+    }
+
+    /// Same as `alloc()`, but returns `OsError::NoMemory` instead of panicking
+    /// if the allocator fails to allocate a page. Nothing is mapped then.
     ///
-    /// TODO. use Result<T> and make it failurable
-    /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    /// # Panics
+    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
+    /// Panics if the virtual address has already been allocated.
+    pub fn try_alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> OsResult<&mut [u8]> {
         if va.as_usize() < USER_IMG_BASE {
             panic!("va < USER_IMG_BASE: 0x{:x}", va.as_u64());
         }
@@ -277,13 +395,21 @@ impl UserPageTable {
         let addr = unsafe { ALLOCATOR.alloc(Page::layout()) as u64 };
         // kprintln!("allocated at 0x{:x}", addr);
         if addr == 0 {
-            panic!("allocation failed");
+            return Err(OsError::NoMemory);
         }
         let mut entry = RawL3Entry::new(0);
         entry.set_masked(addr as u64, RawL3Entry::ADDR);
         entry.set_bit(RawL3Entry::AF);
         entry.set_value(EntrySh::ISh, RawL3Entry::SH);
-        entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        let (ap, uxn) = match perm {
+            PagePerm::RW => (EntryPerm::USER_RW, 1),
+            PagePerm::RO => (EntryPerm::USER_RO, 1),
+            PagePerm::RX => (EntryPerm::USER_RO, 0),
+            PagePerm::RWX => (EntryPerm::USER_RW, 0),
+        };
+        entry.set_value(ap, RawL3Entry::AP);
+        entry.set_value(uxn, RawL3Entry::UXN);
+        entry.set_bit(RawL3Entry::PXN);
         entry.set_value(EntryAttr::Mem, RawL3Entry::ATTR);
         entry.set_value(EntryType::Table, RawL3Entry::TYPE);
         entry.set_bit(RawL3Entry::VALID);
@@ -293,7 +419,179 @@ impl UserPageTable {
         //     va_offset.as_u64(),
         //     entry
         // );
-        unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) }
+        Ok(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) })
+    }
+
+    /// Unmaps the page at the given virtual address and frees it, unless it is
+    /// still shared with another table. Stale translations of the page are
+    /// flushed from the TLB when returning to user space.
+    ///
+    /// # Panics
+    /// Panics if the virtual address is lower than `USER_IMG_BASE`.
+    /// Panics if the virtual address has not been allocated.
+    pub fn dealloc(&mut self, va: VirtualAddr) {
+        if va.as_usize() < USER_IMG_BASE {
+            panic!("va < USER_IMG_BASE: 0x{:x}", va.as_u64());
+        }
+        let va_offset = va - VirtualAddr::from(USER_IMG_BASE);
+        let addr = match self.0.get_page_addr(va_offset) {
+            Some(addr) => addr,
+            None => panic!("va not allocated: 0x{:x}", va.as_u64()),
+        };
+        self.0.set_entry(va_offset, RawL3Entry::new(0));
+        free_page(addr);
+    }
+
+    /// Returns a copy of this page table mapping the same virtual addresses
+    /// to the same pages, shared until one of the tables writes to them.
+    ///
+    /// Writable pages become read-only copy-on-write pages in both tables:
+    /// the first write to one of them faults, and `unshare()` gives the
+    /// writing table its own copy of the page. Stale translations of this
+    /// table are flushed from the TLB when returning to user space.
+    ///
+    /// Returns `OsError::NoMemory` without changing this table if the new
+    /// table cannot be allocated.
+    pub fn share(&mut self) -> OsResult<UserPageTable> {
+        let mut copy = UserPageTable(PageTable::try_new(EntryPerm::USER_RW)?);
+        for (table, copy_table) in self.0.l3.iter_mut().zip(copy.0.l3.iter_mut()) {
+            for (entry, copy_entry) in table.entries.iter_mut().zip(copy_table.entries.iter_mut()) {
+                let addr = match entry.get_page_addr() {
+                    Some(addr) => addr,
+                    None => continue,
+                };
+                if entry.is_user_writable() {
+                    entry.0.set_value(EntryPerm::USER_RO, RawL3Entry::AP);
+                    entry.0.set_bit(RawL3Entry::COW);
+                }
+                SHARED_PAGES.share(addr);
+                *copy_entry = *entry;
+            }
+        }
+        Ok(copy)
+    }
+
+    /// Makes the copy-on-write page at the user virtual address `va` writable
+    /// again in this table. The page is copied first if another table still
+    /// shares it.
+    ///
+    /// Returns `Ok(false)` without changing anything if `va` is not in a
+    /// copy-on-write page. Otherwise, `Ok(true)` is returned.
+    ///
+    /// Returns `OsError::NoMemory` without changing anything if the page needs
+    /// to be copied and the allocator fails to allocate a page.
+    pub fn unshare(&mut self, va: VirtualAddr) -> OsResult<bool> {
+        if va.as_usize() < USER_IMG_BASE {
+            return Ok(false);
+        }
+        let va_offset = VirtualAddr::from((va.as_usize() & PAGE_MASK) - USER_IMG_BASE);
+        let mut entry = self.0.get_entry(va_offset).0;
+        let addr = match L3Entry(entry).get_page_addr() {
+            Some(addr) if entry.get_value(RawL3Entry::COW) != 0 => addr,
+            _ => return Ok(false),
+        };
+        if SHARED_PAGES.is_shared(addr) {
+            let page = unsafe { ALLOCATOR.alloc(Page::layout()) };
+            if page.is_null() {
+                return Err(OsError::NoMemory);
+            }
+            unsafe {
+                core::ptr::copy_nonoverlapping(addr.as_ptr(), page, PAGE_SIZE);
+            }
+            entry.set_masked(page as u64, RawL3Entry::ADDR);
+        }
+        SHARED_PAGES.release(addr);
+        entry.set_value(EntryPerm::USER_RW, RawL3Entry::AP);
+        entry.clear_bit(RawL3Entry::COW);
+        self.0.set_entry(va_offset, entry);
+        tlb_flush();
+        Ok(true)
+    }
+
+    /// Returns `true` if the `len` bytes starting at the virtual address `va`
+    /// lie in the user address space and every page they span is allocated.
+    /// Otherwise, `false` is returned.
+    pub fn is_mapped(&self, va: VirtualAddr, len: usize) -> bool {
+        self.all_pages(va, len, |entry| entry.is_valid())
+    }
+
+    /// Returns `true` if the `len` bytes starting at the virtual address `va`
+    /// lie in the user address space and every page they span is allocated and
+    /// writable from EL0. Otherwise, `false` is returned.
+    pub fn is_writable(&self, va: VirtualAddr, len: usize) -> bool {
+        self.all_pages(va, len, |entry| entry.is_user_writable())
+    }
+
+    /// Unshares the copy-on-write pages spanned by the `len` bytes starting at
+    /// the virtual address `va`, then checks that they are all writable from
+    /// EL0, as `is_writable()` does.
+    ///
+    /// Returns `OsError::BadAddress` if they are not, and `OsError::NoMemory`
+    /// if a page cannot be copied.
+    pub fn make_writable(&mut self, va: VirtualAddr, len: usize) -> OsResult<()> {
+        if !self.is_mapped(va, len) {
+            return Err(OsError::BadAddress);
+        }
+        if len > 0 {
+            let first_page = va.as_usize() & PAGE_MASK;
+            for page in (first_page..va.as_usize() + len).step_by(PAGE_SIZE) {
+                self.unshare(VirtualAddr::from(page))?;
+            }
+        }
+        if self.is_writable(va, len) {
+            Ok(())
+        } else {
+            Err(OsError::BadAddress)
+        }
+    }
+
+    /// Returns `true` if the `len` bytes starting at the virtual address `va`
+    /// lie in the user address space and `f` returns `true` for the entry of
+    /// every page they span. Otherwise, `false` is returned.
+    fn all_pages<F: Fn(&L3Entry) -> bool>(&self, va: VirtualAddr, len: usize, f: F) -> bool {
+        if va.as_usize() < USER_IMG_BASE {
+            return false;
+        }
+        if len == 0 {
+            return true;
+        }
+        let last = match va.as_usize().checked_add(len - 1) {
+            Some(last) => last,
+            None => return false,
+        };
+        let first_page = va.as_usize() & PAGE_MASK;
+        (first_page..=last)
+            .step_by(PAGE_SIZE)
+            .all(|page| f(&self.0.get_entry(VirtualAddr::from(page - USER_IMG_BASE))))
+    }
+
+    /// Copies `buf` to the user virtual address `va` through the physical
+    /// pages backing it, so the table does not need to be the active one.
+    /// Copy-on-write pages are unshared before being written.
+    ///
+    /// Returns `OsError::BadAddress` without copying anything if the
+    /// destination range is not mapped, and `OsError::NoMemory` if a
+    /// copy-on-write page cannot be copied, in which case only the bytes
+    /// before that page are copied.
+    pub fn copy_to(&mut self, va: VirtualAddr, buf: &[u8]) -> OsResult<()> {
+        if !self.is_mapped(va, buf.len()) {
+            return Err(OsError::BadAddress);
+        }
+        let mut copied = 0;
+        while copied < buf.len() {
+            let addr = va.as_usize() + copied;
+            let offset = addr & !PAGE_MASK;
+            let len = core::cmp::min(PAGE_SIZE - offset, buf.len() - copied);
+            self.unshare(VirtualAddr::from(addr))?;
+            let page_va = VirtualAddr::from((addr & PAGE_MASK) - USER_IMG_BASE);
+            let page = self.0.get_page_addr(page_va).unwrap();
+            unsafe {
+                let dst = (page.as_usize() + offset) as *mut u8;
+                core::ptr::copy_nonoverlapping(buf[copied..].as_ptr(), dst, len);
+            }
+            copied += len;
+        }
+        Ok(())
     }
 }
 
@@ -330,11 +628,8 @@ impl Drop for UserPageTable {
     fn drop(&mut self) {
         // kprintln!("Drop UserPageTable");
         for entry in self.0.into_iter() {
-            if entry.is_valid() {
-                let addr = entry.get_page_addr().unwrap();
-                unsafe {
-                    ALLOCATOR.dealloc(addr.as_u64() as *mut u8, Page::layout());
-                }
+            if let Some(addr) = entry.get_page_addr() {
+                free_page(addr);
             }
         }
     }
@@ -35,6 +35,18 @@ pub fn sev() {
     unsafe { asm!("sev" ::::"volatile") };
 }
 
+/// Invalidate all EL1&0 TLB entries after page table updates
+#[inline(always)]
+pub fn tlb_flush() {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vmalle1
+              dsb ish
+              isb"
+             :::: "volatile")
+    };
+}
+
 /// Enable (unmask) interrupts
 #[inline(always)]
 pub unsafe fn sti() {
@@ -61,6 +61,9 @@ defbit!(
 defbit!(
     RawL3Entry,
     [
+        COW[55 - 55], // software use: shared copy-on-write page
+        UXN[54 - 54],
+        PXN[53 - 53],
         ADDR[47 - 16],
         AF[10 - 10],
         SH[09 - 08],
@@ -212,6 +215,23 @@ impl fmt::Debug for RawL3Entry {
             }
         )?;
 
+        write!(
+            f,
+            "|{}{}",
+            match self.get_value(RawL3Entry::PXN) {
+                0 => "PX",
+                _ => "PXN",
+            },
+            match self.get_value(RawL3Entry::UXN) {
+                0 => "UX",
+                _ => "UXN",
+            }
+        )?;
+
+        if self.get_value(RawL3Entry::COW) != 0 {
+            write!(f, "|COW")?;
+        }
+
         // NS    [05-05],
 
         write!(
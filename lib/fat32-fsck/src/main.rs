@@ -0,0 +1,114 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use fat32::gpt::GuidPartitionTable;
+use fat32::vfat::{self, VFat, VFatHandle};
+use fat32::MasterBootRecord;
+use structopt;
+use structopt_derive::StructOpt;
+
+use structopt::StructOpt;
+
+/// Exit status when no problems were found.
+const EXIT_CLEAN: i32 = 0;
+/// Exit status when problems were found and repaired.
+const EXIT_REPAIRED: i32 = 1;
+/// Exit status when problems were found and left as is.
+const EXIT_UNREPAIRED: i32 = 4;
+/// Exit status when the image could not be checked.
+const EXIT_FAILED: i32 = 8;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Check (and repair) the FAT file system of a disk image.")]
+struct Opt {
+    #[structopt(short = "r", long = "repair", help = "Repair the problems found")]
+    repair: bool,
+
+    #[structopt(
+        short = "p",
+        long = "partition",
+        parse(try_from_str),
+        help = "Index of the partition to check (defaults to the first FAT partition)"
+    )]
+    partition: Option<usize>,
+
+    #[structopt(help = "Path to the disk image", parse(from_os_str))]
+    image: PathBuf,
+}
+
+#[derive(Clone)]
+struct FsckVFatHandle(Arc<Mutex<VFat<Self>>>);
+
+impl fmt::Debug for FsckVFatHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FsckVFatHandle")
+    }
+}
+
+impl VFatHandle for FsckVFatHandle {
+    fn new(val: VFat<FsckVFatHandle>) -> Self {
+        FsckVFatHandle(Arc::new(Mutex::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut VFat<FsckVFatHandle>) -> R) -> R {
+        f(&mut self.0.lock().expect("all okay"))
+    }
+}
+
+fn mount(mut image: File, partition: Option<usize>) -> Result<FsckVFatHandle, vfat::Error> {
+    let index = match partition {
+        Some(index) => index,
+        None => return VFat::from(image),
+    };
+    let mbr = MasterBootRecord::from(&mut image)?;
+    if GuidPartitionTable::is_protective(&mbr) {
+        VFat::from_gpt_part(image, index)
+    } else {
+        VFat::from_mbr_part(image, index)
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let image = OpenOptions::new()
+        .read(true)
+        .write(opt.repair)
+        .open(&opt.image)
+        .expect("unable to open image");
+
+    let vfat = match mount(image, opt.partition) {
+        Ok(vfat) => vfat,
+        Err(e) => {
+            eprintln!("{}: failed to mount: {:?}", opt.image.display(), e);
+            process::exit(EXIT_FAILED);
+        }
+    };
+    let problems = match vfat.lock(|vfat| vfat.check(opt.repair)) {
+        Ok(problems) => problems,
+        Err(e) => {
+            eprintln!("{}: check failed: {:?}", opt.image.display(), e);
+            process::exit(EXIT_FAILED);
+        }
+    };
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: clean", opt.image.display());
+        process::exit(EXIT_CLEAN);
+    } else if opt.repair {
+        println!(
+            "{}: {} problems repaired",
+            opt.image.display(),
+            problems.len()
+        );
+        process::exit(EXIT_REPAIRED);
+    } else {
+        println!("{}: {} problems found", opt.image.display(), problems.len());
+        process::exit(EXIT_UNREPAIRED);
+    }
+}
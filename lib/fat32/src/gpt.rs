@@ -0,0 +1,307 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+use shim::const_assert_size;
+use shim::io;
+
+use crate::mbr::{self, MasterBootRecord};
+use crate::traits::BlockDevice;
+
+/// MBR partition type of the protective partition covering a GPT disk.
+const PROTECTIVE_PARTITION_TYPE: u8 = 0xEE;
+
+const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+
+/// Maximum number of partition entries and size of an entry accepted in a
+/// GPT header, bounding the size of the partition entry array read from disk.
+const MAX_PARTITION_ENTRIES: usize = 128;
+const MAX_PARTITION_ENTRY_SIZE: usize = 1024;
+
+/// A GUID, stored in its on-disk mixed-endian byte order.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The unused partition entry type.
+    pub const UNUSED: Guid = Guid([0; 16]);
+    /// EFI system partition: C12A7328-F81F-11D2-BA4B-00A0C93EC93B.
+    pub const EFI_SYSTEM: Guid = Guid([
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9,
+        0x3B,
+    ]);
+    /// Microsoft basic data partition, used for FAT file systems:
+    /// EBD0A0A2-B9E5-4433-87C0-68B6B72699C7.
+    pub const BASIC_DATA: Guid = Guid([
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99,
+        0xC7,
+    ]);
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+        for byte in &b[10..] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+const_assert_size!(Guid, 16);
+
+/// The GPT header, stored in LBA 1 and in the last LBA of the disk.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct GptHeader {
+    signature: [u8; 8],
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    _reserved: u32,
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub partition_entry_lba: u64,
+    pub num_partition_entries: u32,
+    pub partition_entry_size: u32,
+    pub partition_entries_crc32: u32,
+}
+
+const_assert_size!(GptHeader, 92);
+
+impl fmt::Debug for GptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptHeader")
+            .field("revision", &{ self.revision })
+            .field("my_lba", &{ self.my_lba })
+            .field("alternate_lba", &{ self.alternate_lba })
+            .field("first_usable_lba", &{ self.first_usable_lba })
+            .field("last_usable_lba", &{ self.last_usable_lba })
+            .field("disk_guid", &{ self.disk_guid })
+            .field("partition_entry_lba", &{ self.partition_entry_lba })
+            .field("num_partition_entries", &{ self.num_partition_entries })
+            .field("partition_entry_size", &{ self.partition_entry_size })
+            .finish()
+    }
+}
+
+/// An entry of the GPT partition entry array.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct GptPartitionEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    name: [u16; 36],
+}
+
+const_assert_size!(GptPartitionEntry, 128);
+
+impl GptPartitionEntry {
+    /// Returns `true` if this entry describes a partition.
+    pub fn is_used(&self) -> bool {
+        self.type_guid != Guid::UNUSED
+    }
+
+    /// Returns the partition name (label), decoded from UTF-16.
+    pub fn name(&self) -> String {
+        let name = self.name;
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        core::char::decode_utf16(name[..len].iter().cloned())
+            .map(|c| c.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+
+    /// Returns the number of sectors of the partition.
+    pub fn num_sectors(&self) -> u64 {
+        (self.last_lba + 1).saturating_sub(self.first_lba)
+    }
+}
+
+impl fmt::Debug for GptPartitionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptPartitionEntry")
+            .field("type_guid", &{ self.type_guid })
+            .field("unique_guid", &{ self.unique_guid })
+            .field("first_lba", &{ self.first_lba })
+            .field("last_lba", &{ self.last_lba })
+            .field("attributes", &{ self.attributes })
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an I/O error while reading the partition table.
+    Io(io::Error),
+    /// The MBR in LBA 0 could not be read.
+    Mbr(mbr::Error),
+    /// The MBR does not contain a protective partition.
+    NoProtectiveMbr,
+    /// The GPT header magic signature was invalid.
+    BadSignature,
+    /// The GPT header is inconsistent.
+    BadHeader(&'static str),
+    /// The CRC32 of the GPT header did not match.
+    BadHeaderCrc,
+    /// The CRC32 of the partition entry array did not match.
+    BadEntriesCrc,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<mbr::Error> for Error {
+    fn from(error: mbr::Error) -> Error {
+        Error::Mbr(error)
+    }
+}
+
+/// A parsed GUID partition table: its header and partition entry array.
+#[derive(Debug, Clone)]
+pub struct GuidPartitionTable {
+    pub header: GptHeader,
+    pub entries: Vec<GptPartitionEntry>,
+}
+
+impl GuidPartitionTable {
+    /// Returns `true` if `mbr` is a protective MBR, i.e. the disk uses a GPT.
+    pub fn is_protective(mbr: &MasterBootRecord) -> bool {
+        mbr.partition_table
+            .iter()
+            .any(|part| part.partition_type == PROTECTIVE_PARTITION_TYPE)
+    }
+
+    /// Reads and returns the GUID partition table of `device`.
+    ///
+    /// The primary header in LBA 1 is used if it is valid. Otherwise the
+    /// backup header in the last LBA of the disk, as covered by the protective
+    /// MBR partition, is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NoProtectiveMbr` if the MBR has no protective partition. If
+    /// neither the primary nor the backup table is valid, the error of the
+    /// primary one is returned: `BadSignature`, `BadHeader`, `BadHeaderCrc`
+    /// or `BadEntriesCrc` for an invalid table, or `Io(err)` if the I/O error
+    /// `err` occured while reading it.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        let mbr = MasterBootRecord::from(&mut device)?;
+        let protective = match mbr
+            .partition_table
+            .iter()
+            .find(|part| part.partition_type == PROTECTIVE_PARTITION_TYPE)
+        {
+            Some(part) => *part,
+            None => return Err(Error::NoProtectiveMbr),
+        };
+        let primary_err = match GuidPartitionTable::read_at(&mut device, 1) {
+            Ok(gpt) => return Ok(gpt),
+            Err(e) => e,
+        };
+        let backup_lba =
+            (protective.relative_sector as u64 + protective.total_sectors as u64).saturating_sub(1);
+        GuidPartitionTable::read_at(&mut device, backup_lba).map_err(|_| primary_err)
+    }
+
+    /// Reads the header at `lba` and the partition entry array it refers to.
+    fn read_at<T: BlockDevice>(device: &mut T, lba: u64) -> Result<GuidPartitionTable, Error> {
+        let sector_size = device.sector_size() as usize;
+        let mut sector_data = vec![0; sector_size];
+        device.read_sector(lba, &mut sector_data)?;
+        let header = unsafe { *{ sector_data.as_ptr() as *const GptHeader } };
+        if header.signature != GPT_SIGNATURE {
+            return Err(Error::BadSignature);
+        }
+        let header_size = header.header_size as usize;
+        if header_size < size_of::<GptHeader>() || header_size > sector_size {
+            return Err(Error::BadHeader("invalid header size"));
+        }
+        let mut header_data = sector_data[..header_size].to_vec();
+        header_data[16..20].copy_from_slice(&[0; 4]);
+        if crc32(&header_data) != header.header_crc32 {
+            return Err(Error::BadHeaderCrc);
+        }
+        if header.my_lba != lba {
+            return Err(Error::BadHeader("header LBA mismatch"));
+        }
+        let entry_size = header.partition_entry_size as usize;
+        if entry_size < size_of::<GptPartitionEntry>()
+            || entry_size > MAX_PARTITION_ENTRY_SIZE
+            || entry_size % 8 != 0
+        {
+            return Err(Error::BadHeader("invalid partition entry size"));
+        }
+        let num_entries = header.num_partition_entries as usize;
+        if num_entries > MAX_PARTITION_ENTRIES {
+            return Err(Error::BadHeader("too many partition entries"));
+        }
+
+        let entries_len = num_entries
+            .checked_mul(entry_size)
+            .ok_or(Error::BadHeader("partition entry array too large"))?;
+        let sectors = (entries_len + sector_size - 1) / sector_size;
+        let mut entries_data = Vec::with_capacity(sectors * sector_size);
+        for i in 0..sectors {
+            device.read_all_sector(header.partition_entry_lba + i as u64, &mut entries_data)?;
+        }
+        if entries_data.len() < entries_len {
+            return Err(Error::BadHeader("partition entry array out of the disk"));
+        }
+        if crc32(&entries_data[..entries_len]) != header.partition_entries_crc32 {
+            return Err(Error::BadEntriesCrc);
+        }
+        let entries = entries_data[..entries_len]
+            .chunks(entry_size)
+            .map(|data| unsafe { *{ data.as_ptr() as *const GptPartitionEntry } })
+            .collect();
+        Ok(GuidPartitionTable { header, entries })
+    }
+
+    /// Returns the used partition entries along with their index in the
+    /// partition entry array.
+    pub fn partitions(&self) -> impl Iterator<Item = (usize, &GptPartitionEntry)> {
+        self.entries.iter().enumerate().filter(|(_, e)| e.is_used())
+    }
+
+    /// Returns the first partition of type `type_guid`.
+    pub fn find_by_type(&self, type_guid: &Guid) -> Option<&GptPartitionEntry> {
+        self.partitions()
+            .map(|(_, e)| e)
+            .find(|e| e.type_guid == *type_guid)
+    }
+
+    /// Returns the first partition named `label`.
+    pub fn find_by_label(&self, label: &str) -> Option<&GptPartitionEntry> {
+        self.partitions()
+            .map(|(_, e)| e)
+            .find(|e| e.name() == label)
+    }
+}
+
+/// Computes the CRC32 (IEEE 802.3) checksum of `data` as used by GPT.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
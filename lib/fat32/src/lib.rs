@@ -10,6 +10,7 @@ extern crate alloc;
 #[cfg(not(target_endian = "little"))]
 compile_error!("only little endian platforms supported");
 
+pub mod gpt;
 mod mbr;
 #[cfg(test)]
 mod tests;
@@ -47,6 +47,26 @@ pub struct PartitionEntry {
     pub total_sectors: u32,
 }
 
+impl PartitionEntry {
+    /// Returns a non-bootable partition entry of type `partition_type` that
+    /// is only addressed by LBA.
+    pub fn new(partition_type: u8, relative_sector: u32, total_sectors: u32) -> PartitionEntry {
+        // CHS addresses too large to be used, telling to use the LBA instead.
+        let chs = CHS {
+            head: 0xFE,
+            _sector_cylinder: [0xFF, 0xFF],
+        };
+        PartitionEntry {
+            boot_indicator: 0,
+            starting_chs: chs,
+            partition_type,
+            ending_chs: chs,
+            relative_sector,
+            total_sectors,
+        }
+    }
+}
+
 impl Debug for PartitionEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("PartitionEntry")
@@ -100,6 +120,16 @@ impl From<io::Error> for Error {
 }
 
 impl MasterBootRecord {
+    /// Returns an MBR without bootstrap code holding `partition_table`.
+    pub fn new(partition_table: [PartitionEntry; 4]) -> MasterBootRecord {
+        MasterBootRecord {
+            bootstrap: [0; 436],
+            disk_id: [0; 10],
+            partition_table,
+            signature: [0x55, 0xAA],
+        }
+    }
+
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
     /// # Errors
@@ -502,6 +502,866 @@ fn sd_img_test() {
     println!("--- END ---");
 }
 
+/// A `BlockDevice` over an in-memory copy of an image that can be shared by
+/// multiple `VFat` instances, so that written data can be read back from a
+/// freshly mounted file system.
+#[derive(Debug, Clone)]
+struct SharedImage(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl SharedImage {
+    fn new(data: Vec<u8>) -> Self {
+        SharedImage(Arc::new(Mutex::new(Cursor::new(data))))
+    }
+
+    fn from_resource(mut file: ::std::fs::File) -> Self {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).expect("read resource data");
+        SharedImage::new(data)
+    }
+
+    fn mount(&self) -> StdVFatHandle {
+        VFat::<StdVFatHandle>::from_mbr_part0(self.clone())
+            .expect("failed to initialize VFAT from image")
+    }
+}
+
+impl BlockDevice for SharedImage {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write_sector(n, buf)
+    }
+}
+
+fn read_all<T: File>(mut file: T) -> Vec<u8> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read file");
+    assert_eq!(data.len() as u64, file.size());
+    data
+}
+
+#[test]
+fn test_file_write() {
+    let image = SharedImage::from_resource(resource!("mock1.fat32.img"));
+    let vfat = image.mount();
+    let cluster_size = vfat.lock(|vfat| vfat.cluster_size()) as usize;
+    let mut expected = read_all(vfat.open_file("/CS140E").expect("open file"));
+
+    let mut file = vfat.open_file("/CS140E").expect("open file");
+    file.write_all(b"overwritten").expect("overwrite");
+    expected[..11].copy_from_slice(b"overwritten");
+
+    // Extend the file over a few new clusters.
+    let extra: Vec<u8> = (0..3 * cluster_size + 123).map(|i| i as u8).collect();
+    file.seek(io::SeekFrom::End(0)).expect("seek to end");
+    file.write_all(&extra).expect("append");
+    expected.extend_from_slice(&extra);
+    assert_eq!(file.size(), expected.len() as u64);
+    file.sync().expect("sync");
+
+    let data = read_all(vfat.open_file("/CS140E").expect("open file"));
+    assert!(data == expected, "cached read back mismatch");
+
+    let data = read_all(image.mount().open_file("/CS140E").expect("open file"));
+    assert!(data == expected, "read back after remount mismatch");
+}
+
+#[test]
+fn test_file_write_timestamp() {
+    use crate::vfat::{Date, Time, Timestamp};
+
+    let image = SharedImage::from_resource(resource!("mock1.fat32.img"));
+    let vfat = image.mount();
+    vfat.lock(|vfat| {
+        vfat.set_clock(|| Timestamp {
+            date: Date::new(2020, 4, 1),
+            time: Time::new(13, 37, 42),
+        })
+    });
+    {
+        let mut file = vfat.open_file("/CS140E").expect("open file");
+        file.write_all(b"x").expect("write");
+        // Dropping the file updates its directory entry.
+    }
+
+    let entry = image.mount().open("/CS140E").expect("open entry");
+    let modified = entry.metadata().modified();
+    assert_eq!(
+        (modified.year(), modified.month(), modified.day()),
+        (2020, 4, 1)
+    );
+    assert_eq!(
+        (modified.hour(), modified.minute(), modified.second()),
+        (13, 37, 42)
+    );
+}
+
+fn entry_names<D: Dir>(dir: &D) -> Vec<String> {
+    dir.entries()
+        .expect("entries interator")
+        .map(|e| e.name().to_string())
+        .collect()
+}
+
+#[test]
+fn test_create_remove() {
+    let image = SharedImage::from_resource(resource!("mock1.fat32.img"));
+    let vfat = image.mount();
+    {
+        let mut file = vfat.create_file("/hello world.txt").expect("create file");
+        file.write_all(b"hello").expect("write");
+    }
+    vfat.create_dir("/New Folder").expect("create dir");
+    vfat.create_file("/New Folder/NOTES.TXT")
+        .expect("create file in new dir");
+    assert_eq!(
+        vfat.create_file("/HELLO WORLD.TXT").unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+    assert_eq!(
+        vfat.create_file("/bad?name").unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+
+    let vfat = image.mount();
+    let root_names = entry_names(&vfat.open_dir("/").expect("root dir"));
+    assert!(root_names.iter().any(|n| n == "hello world.txt"));
+    assert!(root_names.iter().any(|n| n == "New Folder"));
+    let dir = vfat.open_dir("/New Folder").expect("open new dir");
+    assert_eq!(entry_names(&dir), vec![".", "..", "NOTES.TXT"]);
+    let parent = vfat.open_dir("/New Folder/..").expect("open parent");
+    assert!(entry_names(&parent).iter().any(|n| n == "New Folder"));
+    let file = vfat.open_file("/hello world.txt").expect("open file");
+    assert_eq!(read_all(file), b"hello");
+
+    assert_eq!(
+        vfat.remove("/New Folder").unwrap_err().kind(),
+        io::ErrorKind::Other
+    );
+    vfat.remove("/New Folder/NOTES.TXT").expect("remove file");
+    vfat.remove("/New Folder").expect("remove empty dir");
+    vfat.remove("/hello world.txt").expect("remove file");
+    assert_eq!(
+        vfat.remove("/hello world.txt").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+
+    let vfat = image.mount();
+    assert_eq!(
+        vfat.open("/New Folder").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+    assert_eq!(
+        vfat.open("/hello world.txt").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+
+    // Deleted slots are reused by new entries.
+    let root = vfat.open_dir("/").expect("root dir");
+    let raw_len = root.entries().expect("entries").raw_entries.len();
+    vfat.create_file("/hello world.txt").expect("create file");
+    assert_eq!(root.entries().expect("entries").raw_entries.len(), raw_len);
+}
+
+#[test]
+fn test_create_grows_dir() {
+    let image = SharedImage::from_resource(resource!("mock1.fat32.img"));
+    let vfat = image.mount();
+    vfat.create_dir("/many").expect("create dir");
+    let names: Vec<String> = (0..100).map(|i| format!("long file name {}", i)).collect();
+    for name in &names {
+        vfat.create_file(format!("/many/{}", name))
+            .expect("create file");
+    }
+
+    let vfat = image.mount();
+    let dir = vfat.open_dir("/many").expect("open dir");
+    assert_eq!(&entry_names(&dir)[2..], &names[..]);
+}
+
+#[test]
+fn test_rename() {
+    let image = SharedImage::from_resource(resource!("mock1.fat32.img"));
+    let vfat = image.mount();
+    vfat.create_dir("/a").expect("create dir");
+    vfat.create_dir("/a/b").expect("create dir");
+    {
+        let mut file = vfat.create_file("/a/data.bin").expect("create file");
+        file.write_all(&[0xAB; 1000]).expect("write");
+    }
+
+    vfat.rename("/a/data.bin", "/a/b/A Longer Name.bin")
+        .expect("rename file");
+    vfat.rename("/a/b", "/b").expect("move dir");
+    assert_eq!(
+        vfat.rename("/a", "/a/c").unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+    vfat.create_file("/x").expect("create file");
+    assert_eq!(
+        vfat.rename("/x", "/b/A LONGER NAME.BIN")
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::AlreadyExists
+    );
+    assert_eq!(
+        vfat.rename("/missing", "/y").unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+
+    let vfat = image.mount();
+    assert_eq!(
+        entry_names(&vfat.open_dir("/a").expect("open a")),
+        vec![".", ".."]
+    );
+    let file = vfat
+        .open_file("/b/A Longer Name.bin")
+        .expect("open renamed file");
+    assert_eq!(read_all(file), vec![0xAB; 1000]);
+    let parent = vfat.open_dir("/b/..").expect("open parent of moved dir");
+    assert!(entry_names(&parent).iter().any(|n| n == "x"));
+}
+
+#[test]
+fn test_rename_case_full_dir() {
+    let image = SharedImage::new(fat16_image(2048, 16));
+    let vfat = image.mount();
+    for i in 0..16 {
+        vfat.create_file(format!("/F{}", i)).expect("create file");
+    }
+    {
+        let mut file = vfat.open_file("/F0").expect("open file");
+        file.write_all(b"data").expect("write");
+    }
+
+    // The new name needs an LFN entry, for which the root directory has no
+    // room: the file keeps its old name.
+    vfat.rename("/F0", "/f0")
+        .expect_err("rename into a full directory");
+    let vfat = image.mount();
+    assert!(entry_names(&vfat.open_dir("/").expect("open root"))
+        .iter()
+        .any(|n| n == "F0"));
+    assert_eq!(read_all(vfat.open_file("/F0").expect("open file")), b"data");
+
+    vfat.remove("/F1").expect("remove file");
+    vfat.remove("/F2").expect("remove file");
+    vfat.rename("/F0", "/f0").expect("rename case");
+    let names = entry_names(&vfat.open_dir("/").expect("open root"));
+    assert!(names.iter().any(|n| n == "f0"));
+    assert!(!names.iter().any(|n| n == "F0"));
+}
+
+#[test]
+fn test_create_generated_short_name() {
+    let image = SharedImage::new(fat16_image(2048, 16));
+    let vfat = image.mount();
+    vfat.create_file("/longname.txt").expect("create file");
+    assert_eq!(
+        vfat.create_file("/LONGNA~1.TXT")
+            .expect_err("create over a short name")
+            .kind(),
+        io::ErrorKind::AlreadyExists
+    );
+    vfat.create_file("/LONGNA~2.TXT").expect("create file");
+    let names = entry_names(&vfat.open_dir("/").expect("open root"));
+    assert_eq!(names, vec!["longname.txt", "LONGNA~2.TXT"]);
+
+    // An entry may take the short name it already has.
+    vfat.rename("/longname.txt", "/LONGNA~1.TXT")
+        .expect("rename to the short name");
+    let mut names = entry_names(&vfat.open_dir("/").expect("open root"));
+    names.sort();
+    assert_eq!(names, vec!["LONGNA~1.TXT", "LONGNA~2.TXT"]);
+}
+
+/// Builds an empty FAT12 or FAT16 image (depending on `total_sectors`) with
+/// one sector per cluster, two FATs and a root directory region of
+/// `rootdir_entries` entries, in a partition starting at sector 1.
+fn fat16_image(total_sectors: u16, rootdir_entries: u16) -> Vec<u8> {
+    use vfat::FatType;
+
+    let fat_type = FatType::from_clusters(total_sectors as u32);
+    let sectors_per_fat = ((total_sectors as u32 + 2) * fat_type.entry_bits() / 8 + 511) / 512;
+    let mut image = vec![0u8; (total_sectors as usize + 1) * 512];
+
+    let part = &mut image[446..462];
+    part[4] = if fat_type == FatType::Fat12 {
+        0x01
+    } else {
+        0x06
+    };
+    part[8..12].copy_from_slice(&1u32.to_le_bytes());
+    part[12..16].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let bpb = &mut image[512..1024];
+    bpb[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    bpb[3..11].copy_from_slice(b"CS140E  ");
+    bpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+    bpb[13] = 1;
+    bpb[14..16].copy_from_slice(&1u16.to_le_bytes());
+    bpb[16] = 2;
+    bpb[17..19].copy_from_slice(&rootdir_entries.to_le_bytes());
+    bpb[19..21].copy_from_slice(&total_sectors.to_le_bytes());
+    bpb[21] = 0xF8;
+    bpb[22..24].copy_from_slice(&(sectors_per_fat as u16).to_le_bytes());
+    bpb[28..32].copy_from_slice(&1u32.to_le_bytes());
+    bpb[36] = 0x80;
+    bpb[38] = 0x29;
+    bpb[39..43].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    bpb[43..54].copy_from_slice(b"FAT16 TEST ");
+    bpb[54..62].copy_from_slice(b"FAT16   ");
+    bpb[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let reserved: &[u8] = match fat_type {
+        FatType::Fat12 => &[0xF8, 0xFF, 0xFF],
+        _ => &[0xF8, 0xFF, 0xFF, 0xFF],
+    };
+    for fat in 0..2 {
+        let start = (2 + fat * sectors_per_fat as usize) * 512;
+        image[start..start + reserved.len()].copy_from_slice(reserved);
+    }
+    image
+}
+
+#[test]
+fn test_fat_entry_status() {
+    use vfat::{FatEntry, FatType, Status};
+
+    assert_eq!(FatEntry(0x000, FatType::Fat12).status(), Status::Free);
+    assert_eq!(
+        FatEntry(0x123, FatType::Fat12).status(),
+        Status::Data(0x123.into())
+    );
+    assert_eq!(FatEntry(0xFF7, FatType::Fat12).status(), Status::Bad);
+    assert!(match FatEntry(0xFF8, FatType::Fat12).status() {
+        Status::Eoc(_) => true,
+        _ => false,
+    });
+    assert_eq!(
+        FatEntry(0x0FF8, FatType::Fat16).status(),
+        Status::Data(0x0FF8.into())
+    );
+    assert_eq!(FatEntry(0xFFF7, FatType::Fat16).status(), Status::Bad);
+    assert!(match FatEntry(0xFFFF, FatType::Fat16).status() {
+        Status::Eoc(_) => true,
+        _ => false,
+    });
+    assert_eq!(
+        FatEntry(0xFFFF, FatType::Fat32).status(),
+        Status::Data(0xFFFF.into())
+    );
+}
+
+#[test]
+fn test_fat12_entries() {
+    use vfat::{FatType, Status};
+
+    let mut data = fat16_image(2048, 16);
+    // Chain 2 -> 3 -> 4 -> EOC: entries 2 and 3 share their middle byte.
+    data[1024 + 3..1024 + 9].copy_from_slice(&[0x03, 0x40, 0x00, 0xFF, 0x0F, 0x00]);
+    let vfat = SharedImage::new(data).mount();
+    vfat.lock(|vfat| {
+        assert_eq!(vfat.fat_type(), FatType::Fat12);
+        assert_eq!(
+            vfat.fat_entry(2.into()).unwrap().status(),
+            Status::Data(3.into())
+        );
+        assert_eq!(
+            vfat.fat_entry(3.into()).unwrap().status(),
+            Status::Data(4.into())
+        );
+        assert_eq!(
+            vfat.fat_entry(4.into()).unwrap().status(),
+            Status::Eoc(0x0fff_ffff)
+        );
+        assert_eq!(vfat.fat_entry(5.into()).unwrap().status(), Status::Free);
+
+        vfat.set_fat_entry(3.into(), 0xABC).unwrap();
+        assert_eq!(
+            vfat.fat_entry(2.into()).unwrap().status(),
+            Status::Data(3.into())
+        );
+        assert_eq!(vfat.fat_entry(3.into()).unwrap().0, 0xABC);
+        assert_eq!(
+            vfat.fat_entry(4.into()).unwrap().status(),
+            Status::Eoc(0x0fff_ffff)
+        );
+    });
+}
+
+#[test]
+fn test_fat12_fat16_read_write() {
+    use vfat::FatType;
+
+    for &(total_sectors, fat_type) in &[(2048, FatType::Fat12), (16384, FatType::Fat16)] {
+        let image = SharedImage::new(fat16_image(total_sectors, 16));
+        let vfat = image.mount();
+        assert_eq!(vfat.lock(|vfat| vfat.fat_type()), fat_type);
+
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        vfat.create_dir("/DIR").expect("create dir");
+        {
+            let mut file = vfat.create_file("/dir/data.bin").expect("create file");
+            file.write_all(&data).expect("write");
+        }
+        for i in 0..14 {
+            vfat.create_file(format!("/FILE{}", i))
+                .expect("create file");
+        }
+        // "/DIR" and 14 files occupy 15 of the 16 root directory entries.
+        assert_eq!(
+            vfat.create_file("/a long name").unwrap_err().kind(),
+            io::ErrorKind::Other
+        );
+        vfat.create_file("/LAST").expect("create file");
+
+        let vfat = image.mount();
+        let root = vfat.open_dir("/").expect("open root");
+        assert_eq!(root.entries().expect("entries").count(), 16);
+        let file = vfat.open_file("/dir/data.bin").expect("open file");
+        assert_eq!(read_all(file), data);
+        let parent = vfat.open_dir("/dir/..").expect("open parent");
+        assert_eq!(parent.entries().expect("entries").count(), 16);
+    }
+}
+
+/// Wraps `partition` in a GPT disk image where it is the partition with index
+/// 1, of type basic data and named `name`. Partition entry 0 is unused.
+fn gpt_image(partition: &[u8], name: &str) -> Vec<u8> {
+    use crate::gpt::{crc32, Guid};
+
+    let part_sectors = (partition.len() / 512) as u64;
+    let total_sectors = 3 + part_sectors + 2;
+    let mut image = vec![0u8; total_sectors as usize * 512];
+
+    let pmbr = &mut image[446..462];
+    pmbr[4] = 0xEE;
+    pmbr[8..12].copy_from_slice(&1u32.to_le_bytes());
+    pmbr[12..16].copy_from_slice(&(total_sectors as u32 - 1).to_le_bytes());
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let mut entries = vec![0u8; 512];
+    let entry = &mut entries[128..256];
+    entry[0..16].copy_from_slice(&Guid::BASIC_DATA.0);
+    entry[16..32].copy_from_slice(&[0x42; 16]);
+    entry[32..40].copy_from_slice(&3u64.to_le_bytes());
+    entry[40..48].copy_from_slice(&(3 + part_sectors - 1).to_le_bytes());
+    for (i, c) in name.encode_utf16().enumerate() {
+        entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+    }
+    let entries_crc = crc32(&entries);
+
+    let header = |my_lba: u64, alternate_lba: u64, entries_lba: u64| {
+        let mut header = vec![0u8; 512];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&my_lba.to_le_bytes());
+        header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&3u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(3 + part_sectors - 1).to_le_bytes());
+        header[56..72].copy_from_slice(&[0x24; 16]);
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        header
+    };
+    let last = total_sectors - 1;
+    image[512..1024].copy_from_slice(&header(1, last, 2));
+    image[1024..1536].copy_from_slice(&entries);
+    image[1536..1536 + partition.len()].copy_from_slice(partition);
+    let backup_entries = (last as usize - 1) * 512;
+    image[backup_entries..backup_entries + 512].copy_from_slice(&entries);
+    image[last as usize * 512..].copy_from_slice(&header(last, 1, last - 1));
+    image
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crate::gpt::crc32(b""), 0);
+    assert_eq!(crate::gpt::crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_gpt() {
+    use crate::gpt::{Guid, GuidPartitionTable};
+
+    let image = gpt_image(&fat16_image(2048, 16)[512..], "DATA");
+    let gpt = GuidPartitionTable::from(Cursor::new(image.clone())).expect("valid GPT");
+    assert_eq!(gpt.entries.len(), 4);
+    let partitions: Vec<_> = gpt.partitions().collect();
+    assert_eq!(partitions.len(), 1);
+    assert_eq!(partitions[0].0, 1);
+    assert_eq!(partitions[0].1.name(), "DATA");
+    assert_eq!({ partitions[0].1.first_lba }, 3);
+    assert_eq!(partitions[0].1.num_sectors(), 2048);
+    assert!(gpt.find_by_type(&Guid::BASIC_DATA).is_some());
+    assert!(gpt.find_by_type(&Guid::EFI_SYSTEM).is_none());
+
+    let mount = |result: Result<StdVFatHandle, vfat::Error>| {
+        let vfat = result.expect("mount GPT partition");
+        vfat.create_file("/HELLO").expect("create file");
+    };
+    mount(VFat::from_gpt_part(Cursor::new(image.clone()), 1));
+    mount(VFat::from_gpt_type(
+        Cursor::new(image.clone()),
+        &Guid::BASIC_DATA,
+    ));
+    mount(VFat::from_gpt_label(Cursor::new(image.clone()), "DATA"));
+    mount(VFat::from(Cursor::new(image.clone())));
+    assert!(
+        match VFat::<StdVFatHandle>::from_gpt_part(Cursor::new(image.clone()), 0) {
+            Err(vfat::Error::NotFound) => true,
+            _ => false,
+        }
+    );
+    assert!(
+        match VFat::<StdVFatHandle>::from_gpt_label(Cursor::new(image.clone()), "X") {
+            Err(vfat::Error::NotFound) => true,
+            _ => false,
+        }
+    );
+    assert!(
+        match VFat::<StdVFatHandle>::from_mbr_part0(Cursor::new(image.clone())) {
+            Err(vfat::Error::NotFound) => true,
+            _ => false,
+        }
+    );
+}
+
+#[test]
+fn test_gpt_backup_header() {
+    use crate::gpt::{Error, GuidPartitionTable};
+
+    let mut image = gpt_image(&fat16_image(2048, 16)[512..], "DATA");
+    // Corrupt the primary header: the backup header is used instead.
+    image[512 + 40] ^= 0xFF;
+    let gpt = GuidPartitionTable::from(Cursor::new(image.clone())).expect("backup GPT");
+    assert_eq!({ gpt.header.my_lba }, 2048 + 4);
+    VFat::<StdVFatHandle>::from_gpt_label(Cursor::new(image.clone()), "DATA")
+        .expect("mount with backup GPT");
+
+    // Corrupt the backup partition entry array too.
+    let backup_entries = image.len() - 1024;
+    image[backup_entries + 200] ^= 0xFF;
+    assert!(match GuidPartitionTable::from(Cursor::new(image)) {
+        Err(Error::BadHeaderCrc) => true,
+        _ => false,
+    });
+}
+
+#[test]
+fn test_gpt_entry_limits() {
+    use crate::gpt::{crc32, Error, GuidPartitionTable};
+
+    // Sets the entry count and size of both headers, with valid CRCs.
+    let with_entries = |count: u32, size: u32| {
+        let mut image = gpt_image(&fat16_image(2048, 16)[512..], "DATA");
+        let last = image.len() - 512;
+        for &start in [512, last].iter() {
+            let header = &mut image[start..start + 92];
+            header[80..84].copy_from_slice(&count.to_le_bytes());
+            header[84..88].copy_from_slice(&size.to_le_bytes());
+            header[16..20].copy_from_slice(&[0; 4]);
+            let crc = crc32(header);
+            header[16..20].copy_from_slice(&crc.to_le_bytes());
+        }
+        GuidPartitionTable::from(Cursor::new(image))
+    };
+    let bad_header = |result: Result<GuidPartitionTable, Error>| match result {
+        Err(Error::BadHeader(_)) => true,
+        _ => false,
+    };
+    assert!(bad_header(with_entries(u32::max_value(), 128)));
+    assert!(bad_header(with_entries(129, 128)));
+    assert!(bad_header(with_entries(4, 2048)));
+    assert!(bad_header(with_entries(4, 0xFFFF_FFF8)));
+}
+
+#[test]
+fn test_fsck() {
+    use vfat::Problem;
+
+    let image = SharedImage::new(fat16_image(16384, 64));
+    let vfat = image.mount();
+    vfat.create_dir("/DIR").expect("create dir");
+    let long_data = vec![0x11; 3 * 512];
+    vfat.create_file("/DIR/a long file name.txt")
+        .expect("create file")
+        .write_all(&long_data)
+        .expect("write");
+    vfat.create_file("/FILE")
+        .expect("create file")
+        .write_all(&[0x22; 2 * 512])
+        .expect("write");
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check"), vec![]);
+
+    let dir = vfat.open_dir("/DIR").expect("open dir").first_cluster;
+    let long = vfat.open_file("/DIR/a long file name.txt").expect("open");
+    let file = vfat.open_file("/FILE").expect("open").first_cluster;
+    let long_second =
+        vfat.lock(
+            |vfat| match vfat.fat_entry(long.first_cluster).unwrap().status() {
+                vfat::Status::Data(next) => next,
+                _ => panic!("long file has a single cluster"),
+            },
+        );
+    vfat.lock(|vfat| {
+        // A lost chain of two clusters.
+        vfat.set_fat_entry(1000.into(), 1001).unwrap();
+        vfat.set_fat_entry(1001.into(), 0xFFFF).unwrap();
+        // The second cluster of /FILE is linked into the chain of the long file.
+        let file_second = match vfat.fat_entry(file).unwrap().status() {
+            vfat::Status::Data(next) => next,
+            _ => panic!("file has a single cluster"),
+        };
+        vfat.set_fat_entry(file_second, long_second.raw()).unwrap();
+        // The long file gets an extra cluster.
+        let mut last = long_second;
+        while let vfat::Status::Data(next) = vfat.fat_entry(last).unwrap().status() {
+            last = next;
+        }
+        vfat.alloc_cluster(Some(last)).unwrap();
+        // Changing the short name breaks the LFN checksum.
+        vfat.update_dir_entry(dir, 4, |entry| entry.set_short_name(b"BROKEN  TXT"))
+            .unwrap();
+        vfat.flush().unwrap();
+    });
+    {
+        // FAT copy 1 gets a different entry for cluster 3000.
+        let mut data = image.0.lock().unwrap();
+        let fat1 = (2 + 65) * 512 + 3000 * 2;
+        data.get_mut()[fat1] = 0x55;
+    }
+
+    let vfat = image.mount();
+    let problems = vfat.lock(|vfat| vfat.check(false)).expect("check");
+    let expected = vec![
+        Problem::FatMismatch { fat: 1, entries: 1 },
+        Problem::BadLfn {
+            dir: "/DIR".to_string(),
+            index: 4,
+        },
+        Problem::SizeMismatch {
+            path: "/DIR/BROKEN.TXT".to_string(),
+            size: 3 * 512,
+            clusters: 4,
+        },
+        Problem::CrossLinked {
+            cluster: long_second.raw(),
+            first: "/DIR/BROKEN.TXT".to_string(),
+            second: "/FILE".to_string(),
+        },
+        Problem::LostChain {
+            start: 1000,
+            clusters: 2,
+        },
+    ];
+    assert_eq!(problems, expected);
+    for problem in &problems {
+        assert!(!problem.to_string().is_empty());
+    }
+    assert_eq!(
+        vfat.lock(|vfat| vfat.check(true)).expect("repair"),
+        expected
+    );
+
+    let vfat = image.mount();
+    assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check"), vec![]);
+    let file = vfat.open_file("/DIR/BROKEN.TXT").expect("open file");
+    assert_eq!(read_all(file), long_data);
+    let file = vfat.open_file("/FILE").expect("open file");
+    assert_eq!(read_all(file), vec![0x22; 2 * 512]);
+}
+
+#[test]
+fn test_fsck_check_writes_nothing() {
+    use vfat::{format, FormatOptions};
+
+    let total_sectors = 40 * 2048;
+    let image = SharedImage::new(vec![0; total_sectors * 512]);
+    format(
+        image.clone(),
+        total_sectors as u64,
+        &FormatOptions::default(),
+    )
+    .expect("format");
+    let vfat = image.mount();
+    // Both FATs fit in the cache, so that no dirty sector is evicted.
+    vfat.lock(|vfat| vfat.set_cache_capacity(4096))
+        .expect("set cache capacity");
+    vfat.create_file("/FILE")
+        .expect("create file")
+        .write_all(&[0x22; 2 * 512])
+        .expect("write");
+    let lost = vfat.lock(|vfat| vfat.alloc_cluster(None)).expect("alloc");
+
+    // Checking leaves the unflushed changes, including the free cluster
+    // count of the FSInfo sector, in the cache.
+    let before = image.0.lock().unwrap().get_ref().clone();
+    let expected = vec![vfat::Problem::LostChain {
+        start: lost.raw(),
+        clusters: 1,
+    }];
+    assert_eq!(
+        vfat.lock(|vfat| vfat.check(false)).expect("check"),
+        expected
+    );
+    assert!(*image.0.lock().unwrap().get_ref() == before);
+
+    vfat.lock(|vfat| vfat.flush()).expect("flush");
+    let vfat = image.mount();
+    assert_eq!(
+        vfat.lock(|vfat| vfat.check(false)).expect("check"),
+        expected
+    );
+}
+
+#[test]
+fn test_format() {
+    use vfat::{format, FatType, FormatOptions};
+
+    // 40MiB with 512 byte clusters is the smallest default layout for FAT32.
+    let total_sectors = 40 * 2048;
+    let image = SharedImage::new(vec![0xAA; total_sectors * 512]);
+    let options = FormatOptions {
+        volume_label: "scratch".to_string(),
+        volume_id: 0x1234_5678,
+        ..FormatOptions::default()
+    };
+    format(image.clone(), total_sectors as u64, &options).expect("format");
+
+    let mbr = crate::mbr::MasterBootRecord::from(image.clone()).expect("mbr");
+    let part = mbr.partition_table[0];
+    assert_eq!(part.partition_type, 0x0C);
+    assert_eq!({ part.relative_sector }, 2048);
+    assert_eq!({ part.total_sectors } as usize, total_sectors - 2048);
+    let ebpb = vfat::BiosParameterBlock::from(image.clone(), 2048).expect("ebpb");
+    assert_eq!(ebpb.volume_label(), "SCRATCH    ");
+    assert_eq!({ ebpb.volume_id }, 0x1234_5678);
+    let backup = vfat::BiosParameterBlock::from(image.clone(), 2048 + 6).expect("backup ebpb");
+    assert_eq!({ backup.sectors_per_fat_32 }, { ebpb.sectors_per_fat_32 });
+
+    let vfat = image.mount();
+    vfat.lock(|vfat| {
+        assert_eq!(vfat.fat_type(), FatType::Fat32);
+        assert_eq!(vfat.fats(), 2);
+        let fsinfo = vfat.fsinfo().expect("fsinfo").expect("FAT32 has an FSInfo");
+        assert_eq!({ fsinfo.free_count }, vfat.data_clusters() - 1);
+        assert_eq!(vfat.check(false).expect("check"), vec![]);
+    });
+    assert!(entry_names(&vfat.open_dir("/").expect("open root")).is_empty());
+
+    vfat.create_dir("/dir").expect("create dir");
+    vfat.create_file("/dir/file.txt")
+        .expect("create file")
+        .write_all(&[0x33; 1500])
+        .expect("write");
+    let vfat = image.mount();
+    let file = vfat.open_file("/dir/file.txt").expect("open file");
+    assert_eq!(read_all(file), vec![0x33; 1500]);
+}
+
+#[test]
+fn test_format_fat12_fat16() {
+    use vfat::{format, FatType, FormatOptions};
+
+    for &(total_sectors, fat_type) in &[(3 * 2048, FatType::Fat12), (8 * 2048, FatType::Fat16)] {
+        let image = SharedImage::new(vec![0xAA; total_sectors * 512]);
+        let options = FormatOptions {
+            fat_type,
+            volume_label: "small".to_string(),
+            ..FormatOptions::default()
+        };
+        format(image.clone(), total_sectors as u64, &options).expect("format");
+
+        let ebpb = vfat::BiosParameterBlock::from(image.clone(), 2048).expect("ebpb");
+        assert_eq!(ebpb.volume_label(), "SMALL      ");
+        assert_eq!(ebpb.logical_sectors() as usize, total_sectors - 2048);
+        let vfat = image.mount();
+        vfat.lock(|vfat| {
+            assert_eq!(vfat.fat_type(), fat_type);
+            assert!(vfat.fsinfo().expect("fsinfo").is_none());
+            assert_eq!(vfat.check(false).expect("check"), vec![]);
+        });
+        let total = vfat.lock(|vfat| vfat.data_clusters());
+        assert_eq!(vfat.statfs().expect("statfs").free_clusters, total);
+        assert!(entry_names(&vfat.open_dir("/").expect("open root")).is_empty());
+
+        vfat.create_dir("/dir").expect("create dir");
+        vfat.create_file("/dir/file.txt")
+            .expect("create file")
+            .write_all(&[0x33; 1500])
+            .expect("write");
+        let vfat = image.mount();
+        assert_eq!(vfat.lock(|vfat| vfat.check(false)).expect("check"), vec![]);
+        let file = vfat.open_file("/dir/file.txt").expect("open file");
+        assert_eq!(read_all(file), vec![0x33; 1500]);
+    }
+}
+
+#[test]
+fn test_format_invalid() {
+    use vfat::{format, FormatOptions};
+
+    let image = SharedImage::new(vec![0; 40 * 2048 * 512]);
+    let check = |sectors: u64, options: FormatOptions| {
+        let err = format(image.clone(), sectors, &options).expect_err("format must fail");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    };
+    check(8 * 2048, FormatOptions::default());
+    check(
+        40 * 2048,
+        FormatOptions {
+            cluster_size: Some(4096),
+            ..FormatOptions::default()
+        },
+    );
+    check(
+        40 * 2048,
+        FormatOptions {
+            cluster_size: Some(768),
+            ..FormatOptions::default()
+        },
+    );
+    check(
+        40 * 2048,
+        FormatOptions {
+            volume_label: "a label too long".to_string(),
+            ..FormatOptions::default()
+        },
+    );
+    check(
+        40 * 2048,
+        FormatOptions {
+            fats: 0,
+            ..FormatOptions::default()
+        },
+    );
+    check(
+        40 * 2048,
+        FormatOptions {
+            fat_type: vfat::FatType::Fat12,
+            cluster_size: Some(512),
+            ..FormatOptions::default()
+        },
+    );
+    check(
+        3 * 2048,
+        FormatOptions {
+            fat_type: vfat::FatType::Fat16,
+            ..FormatOptions::default()
+        },
+    );
+}
+
 use crate::vfat::cache::{BlockDeviceCached, BlockDevicePartition, Partition};
 
 fn block_device_testdata() -> Cursor<Vec<u8>> {
@@ -622,3 +1482,245 @@ fn block_device_test() {
     );
     assert_eq!([0xAAu8; 512].to_vec(), sector_data.to_vec());
 }
+
+#[test]
+fn block_device_cache_eviction() {
+    use crate::vfat::CacheStats;
+
+    let image = SharedImage::new(block_device_testdata().into_inner());
+    let mut bd = BlockDeviceCached::with_capacity(image.clone(), 2);
+    bd.get_mut(0).expect("get_mut")[0] = 0x11;
+    assert_eq!(bd.get(1).expect("get")[0], 0xBB);
+    assert_eq!(image.0.lock().unwrap().get_ref()[0], 0xAA);
+
+    // Both slots are referenced: the hand clears them and evicts sector 0.
+    assert_eq!(bd.get(2).expect("get")[0], 0xCC);
+    assert_eq!(image.0.lock().unwrap().get_ref()[0], 0x11);
+    assert_eq!(bd.get(1).expect("get")[0], 0xBB);
+    assert_eq!(bd.get(0).expect("get")[0], 0x11);
+    assert_eq!(
+        bd.stats(),
+        CacheStats {
+            hits: 1,
+            misses: 4,
+            evictions: 2,
+            writebacks: 1,
+            prefetched: 0,
+            cached: 2,
+            capacity: 2,
+        }
+    );
+
+    bd.write_sector(3, &[0x44; 512]).expect("write_sector");
+    assert_eq!(image.0.lock().unwrap().get_ref()[3 * 512], 0);
+    bd.flush().expect("flush");
+    assert_eq!(image.0.lock().unwrap().get_ref()[3 * 512], 0x44);
+    bd.set_capacity(1).expect("set_capacity");
+    assert_eq!(bd.stats().cached, 0);
+}
+
+#[test]
+fn test_small_cache_write_read() {
+    use vfat::{format, FormatOptions};
+
+    let total_sectors = 40 * 2048;
+    let image = SharedImage::new(vec![0; total_sectors * 512]);
+    format(
+        image.clone(),
+        total_sectors as u64,
+        &FormatOptions::default(),
+    )
+    .expect("format");
+
+    let data: Vec<u8> = (0..64 * 512).map(|i| (i / 7) as u8).collect();
+    let vfat = image.mount();
+    vfat.lock(|vfat| vfat.set_cache_capacity(4))
+        .expect("set capacity");
+    vfat.create_file("/big.bin")
+        .expect("create file")
+        .write_all(&data)
+        .expect("write");
+    let stats = vfat.cache_stats().expect("cache stats");
+    assert!(stats.cached <= 4);
+    assert!(stats.evictions > 0 && stats.writebacks > 0);
+
+    let vfat = image.mount();
+    let file = vfat.open_file("/big.bin").expect("open file");
+    assert_eq!(read_all(file), data);
+}
+
+#[test]
+fn block_device_cache_readahead() {
+    let data: Vec<u8> = (0..16 * 512).map(|i| (i / 512) as u8).collect();
+    let mut bd = BlockDeviceCached::with_capacity(Cursor::new(data.clone()), 32);
+    bd.set_readahead(8);
+    let mut buf = vec![0; 4 * 512];
+
+    assert_eq!(bd.read_sectors(0, 2, &mut buf).expect("read"), 1024);
+    assert_eq!(&buf[..1024], &data[..1024]);
+    assert_eq!((bd.stats().misses, bd.stats().prefetched), (2, 0));
+
+    // A sequential read also fetches the next 8 sectors.
+    assert_eq!(bd.read_sectors(2, 2, &mut buf).expect("read"), 1024);
+    assert_eq!(&buf[..1024], &data[1024..2048]);
+    assert_eq!((bd.stats().misses, bd.stats().prefetched), (4, 8));
+    assert_eq!(bd.read_sectors(4, 4, &mut buf).expect("read"), 2048);
+    assert_eq!(&buf[..], &data[2048..4096]);
+    assert_eq!(bd.stats().hits, 4);
+
+    // Partial sector reads are bounded by the buffer.
+    let mut small = vec![0; 700];
+    assert_eq!(bd.read_sectors(8, 4, &mut small).expect("read"), 700);
+    assert_eq!(&small[..], &data[8 * 512..8 * 512 + 700]);
+
+    // Read-ahead past the end of the device falls back to the requested run.
+    assert_eq!(bd.read_sectors(12, 2, &mut buf).expect("read"), 1024);
+    assert_eq!(bd.read_sectors(14, 2, &mut buf).expect("read"), 1024);
+    assert_eq!(&buf[..1024], &data[14 * 512..]);
+    assert_eq!(bd.stats().prefetched, 8);
+}
+
+#[test]
+fn test_file_read_runs() {
+    use vfat::{format, FormatOptions};
+
+    let total_sectors = 40 * 2048;
+    let image = SharedImage::new(vec![0; total_sectors * 512]);
+    format(
+        image.clone(),
+        total_sectors as u64,
+        &FormatOptions::default(),
+    )
+    .expect("format");
+
+    // Interleave the clusters of two files so that both are fragmented.
+    let vfat = image.mount();
+    let mut a = vfat.create_file("/A.BIN").expect("create file");
+    let mut b = vfat.create_file("/B.BIN").expect("create file");
+    let a_data: Vec<u8> = (0..10 * 512 + 100).map(|i| (i % 251) as u8).collect();
+    let b_data = vec![0x77; 4 * 512];
+    for (a_chunk, b_chunk) in a_data.chunks(3 * 512).zip(b_data.chunks(512)) {
+        a.write_all(a_chunk).expect("write");
+        b.write_all(b_chunk).expect("write");
+    }
+    drop((a, b));
+
+    let vfat = image.mount();
+    let mut a = vfat.open_file("/A.BIN").expect("open file");
+    let mut buf = vec![0; a_data.len() + 10];
+    assert_eq!(a.read(&mut buf).expect("read"), a_data.len());
+    assert_eq!(&buf[..a_data.len()], &a_data[..]);
+
+    a.seek(io::SeekFrom::Start(700)).expect("seek");
+    let mut buf = vec![0; 2000];
+    a.read_exact(&mut buf).expect("read");
+    assert_eq!(&buf[..], &a_data[700..2700]);
+    assert_eq!(
+        read_all(vfat.open_file("/B.BIN").expect("open file")),
+        b_data
+    );
+}
+
+#[test]
+fn test_free_space() {
+    use vfat::{format, FatType, FormatOptions};
+
+    let total_sectors = 40 * 2048;
+    let image = SharedImage::new(vec![0; total_sectors * 512]);
+    format(
+        image.clone(),
+        total_sectors as u64,
+        &FormatOptions::default(),
+    )
+    .expect("format");
+
+    let vfat = image.mount();
+    let stats = vfat.statfs().expect("statfs");
+    let total = vfat.lock(|vfat| vfat.data_clusters());
+    assert_eq!(stats.fat_type, FatType::Fat32);
+    assert_eq!(stats.total_clusters, total);
+    assert_eq!(stats.free_clusters, total - 1);
+    assert_eq!(stats.total_space(), total as u64 * 512);
+    assert_eq!(
+        vfat.lock(|vfat| vfat.free_space()).expect("free space"),
+        (total as u64 - 1) * 512
+    );
+
+    vfat.create_dir("/DIR").expect("create dir");
+    vfat.create_file("/DIR/FILE")
+        .expect("create file")
+        .write_all(&[0x55; 10 * 512])
+        .expect("write");
+    assert_eq!(vfat.statfs().expect("statfs").free_clusters, total - 12);
+
+    // The count and the hint are persisted in the FSInfo sector.
+    let vfat = image.mount();
+    vfat.lock(|vfat| {
+        let fsinfo = vfat.fsinfo().expect("fsinfo").expect("FAT32 has an FSInfo");
+        assert_eq!({ fsinfo.free_count }, total - 12);
+        assert_eq!({ fsinfo.next_free }, 2 + 12);
+        assert_eq!(vfat.check(false).expect("check"), vec![]);
+    });
+    vfat.remove("/DIR/FILE").expect("remove");
+    assert_eq!(vfat.statfs().expect("statfs").free_clusters, total - 2);
+    let vfat = image.mount();
+    vfat.lock(|vfat| {
+        let fsinfo = vfat.fsinfo().expect("fsinfo").expect("FAT32 has an FSInfo");
+        assert_eq!({ fsinfo.free_count }, total - 2);
+        assert_eq!(vfat.check(false).expect("check"), vec![]);
+    });
+
+    // Allocation starts from the next free cluster hint.
+    vfat.lock(|vfat| {
+        let mut fsinfo = vfat.fsinfo().expect("fsinfo").expect("FAT32 has an FSInfo");
+        fsinfo.next_free = 100;
+        vfat.set_fsinfo(&fsinfo).expect("set fsinfo");
+        vfat.flush().expect("flush");
+    });
+    let vfat = image.mount();
+    let mut file = vfat.create_file("/HINTED").expect("create file");
+    file.write_all(&[1; 512]).expect("write");
+    assert_eq!(file.first_cluster.raw(), 100);
+}
+
+#[test]
+fn test_free_space_scan_read_only() {
+    use vfat::{format, FormatOptions};
+
+    let total_sectors = 40 * 2048;
+    let image = SharedImage::new(vec![0; total_sectors * 512]);
+    format(
+        image.clone(),
+        total_sectors as u64,
+        &FormatOptions::default(),
+    )
+    .expect("format");
+    let vfat = image.mount();
+    vfat.lock(|vfat| {
+        let mut fsinfo = vfat.fsinfo().expect("fsinfo").expect("FAT32 has an FSInfo");
+        fsinfo.free_count = 0xFFFF_FFFF;
+        vfat.set_fsinfo(&fsinfo).expect("set fsinfo");
+        vfat.flush().expect("flush");
+    });
+
+    // Scanning the FAT for an unknown count does not write the FSInfo sector.
+    let before = image.0.lock().unwrap().get_ref().clone();
+    let vfat = image.mount();
+    let total = vfat.lock(|vfat| vfat.data_clusters());
+    assert_eq!(vfat.statfs().expect("statfs").free_clusters, total - 1);
+    vfat.lock(|vfat| vfat.flush()).expect("flush");
+    assert!(*image.0.lock().unwrap().get_ref() == before);
+}
+
+#[test]
+fn test_free_space_fat16() {
+    let image = SharedImage::new(fat16_image(16384, 64));
+    let vfat = image.mount();
+    let free = vfat.statfs().expect("statfs").free_clusters;
+    assert_eq!(free, vfat.lock(|vfat| vfat.data_clusters()));
+    vfat.create_file("/FILE")
+        .expect("create file")
+        .write_all(&[0x66; 3 * 512])
+        .expect("write");
+    assert_eq!(vfat.statfs().expect("statfs").free_clusters, free - 3);
+}
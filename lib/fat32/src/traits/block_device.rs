@@ -21,6 +21,31 @@ pub trait BlockDevice: Send + core::fmt::Debug {
     /// Returns an error if seeking or reading from `self` fails.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize>;
 
+    /// Reads the `count` consecutive sectors starting at sector `n` into
+    /// `buf`.
+    ///
+    /// `count * self.sector_size()` or `buf.len()` bytes, whichever is less,
+    /// are read into `buf`. The number of bytes read is returned. The default
+    /// implementation reads one sector at a time with `read_sector()`; devices
+    /// able to transfer several sectors at once should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sectors(&mut self, n: u64, count: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let len = ::core::cmp::min(buf.len(), count * sector_size);
+        let mut read_bytes = 0;
+        for (i, chunk) in buf[..len].chunks_mut(sector_size).enumerate() {
+            let read = self.read_sector(n + i as u64, chunk)?;
+            read_bytes += read;
+            if read < chunk.len() {
+                break;
+            }
+        }
+        Ok(read_bytes)
+    }
+
     /// Append sector number `n` into `vec`.
     ///
     /// `self.sector_size()` bytes are appended to `vec`. The number of bytes
@@ -68,6 +93,10 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
         (*self).read_sector(n, buf)
     }
 
+    fn read_sectors(&mut self, n: u64, count: usize, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(n, count, buf)
+    }
+
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
@@ -85,6 +114,14 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             Ok(to_read)
         }
 
+        fn read_sectors(&mut self, n: u64, count: usize, buf: &mut [u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_read = ::core::cmp::min(count * sector_size as usize, buf.len());
+            self.seek(io::SeekFrom::Start(n * sector_size))?;
+            self.read_exact(&mut buf[..to_read])?;
+            Ok(to_read)
+        }
+
         fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
             let sector_size = self.sector_size();
             let to_write = ::core::cmp::min(sector_size as usize, buf.len());
@@ -98,5 +135,5 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
 impl_for_read_write_seek!(<'a> shim::io::Cursor<&'a mut [u8]>);
 impl_for_read_write_seek!(shim::io::Cursor<Vec<u8>>);
 impl_for_read_write_seek!(shim::io::Cursor<Box<[u8]>>);
-#[cfg(test)]
+#[cfg(not(feature = "no_std"))]
 impl_for_read_write_seek!(::std::fs::File);
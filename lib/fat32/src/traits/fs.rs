@@ -1,6 +1,7 @@
 use shim::{io, path::Path};
 
 use crate::traits::Metadata;
+use crate::vfat::{CacheStats, FsStats};
 
 /// Trait implemented by files in the file system.
 pub trait File: io::Read + io::Write + io::Seek + Sized {
@@ -113,4 +114,75 @@ pub trait FileSystem: Sized {
             .into_dir()
             .ok_or(io::Error::new(io::ErrorKind::Other, "not a directory"))
     }
+
+    /// Creates a new, empty regular file at `path` and returns it. `path`
+    /// must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// If any component but the last in `path` does not refer to an existing
+    /// directory, an error kind of `NotFound` is returned.
+    ///
+    /// If the last component of `path` is not a valid file name, an error
+    /// kind of `InvalidInput` is returned.
+    ///
+    /// If an entry already exists at `path`, an error kind of `AlreadyExists`
+    /// is returned.
+    fn create_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::File>;
+
+    /// Creates a new, empty directory at `path` and returns it. `path` must be
+    /// absolute.
+    ///
+    /// # Errors
+    ///
+    /// The error conditions are the same as for `create_file()`.
+    fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::Dir>;
+
+    /// Removes the file or directory at `path`. `path` must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry at `path`, an error kind of `NotFound` is
+    /// returned.
+    ///
+    /// If `path` refers to a directory that is not empty, an error kind of
+    /// `Other` is returned.
+    ///
+    /// If `path` refers to the root directory, an error kind of
+    /// `InvalidInput` is returned.
+    fn remove<P: AsRef<Path>>(&self, path: P) -> io::Result<()>;
+
+    /// Renames the entry at `from` to `to`, moving it to another directory if
+    /// needed. Both paths must be absolute.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry at `from` or the parent of `to` is not an
+    /// existing directory, an error kind of `NotFound` is returned.
+    ///
+    /// If an entry already exists at `to`, an error kind of `AlreadyExists`
+    /// is returned.
+    ///
+    /// If `from` is a directory and `to` is inside of it, an error kind of
+    /// `InvalidInput` is returned.
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()>;
+
+    /// Returns the access statistics of the sector cache of this file system,
+    /// or `None` if it does not cache sectors.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Returns the usage of this file system.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error kind of `Other` if the file system does not track its
+    /// usage. All other error values are implementation defined.
+    fn statfs(&self) -> io::Result<FsStats> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "statfs is not supported",
+        ))
+    }
 }
@@ -2,7 +2,7 @@ use alloc::boxed::Box;
 use alloc::vec::Vec;
 use core::cmp;
 use core::fmt;
-use hashbrown::{hash_map::Entry, HashMap};
+use hashbrown::HashMap;
 use shim::io;
 use shim::ioerr;
 
@@ -11,8 +11,11 @@ use crate::traits::BlockDevice;
 
 #[derive(Debug)]
 struct CacheEntry {
+    sector: u64,
     data: Vec<u8>,
     dirty: bool,
+    /// Set when the sector is accessed, cleared by the CLOCK hand.
+    referenced: bool,
 }
 
 #[derive(Debug)]
@@ -71,28 +74,6 @@ impl BlockDevicePartition {
     }
 }
 
-pub fn read_n_sectors(
-    device: &mut dyn BlockDevice,
-    sector: u64,
-    n: usize,
-    buf: &mut [u8],
-) -> io::Result<usize> {
-    let sector_size = device.sector_size() as usize;
-    let buf_len = buf.len();
-    let mut read_bytes = 0;
-    for i in 0..n as usize {
-        let n = device.read_sector(
-            sector + i as u64,
-            &mut buf[i * sector_size..cmp::min(buf_len, (i + 1) * sector_size)],
-        )?;
-        read_bytes += n;
-        if n < sector_size {
-            break;
-        }
-    }
-    Ok(read_bytes)
-}
-
 pub fn write_n_sectors(
     device: &mut dyn BlockDevice,
     sector: u64,
@@ -127,7 +108,19 @@ impl BlockDevice for BlockDevicePartition {
         };
         let factor = self.factor() as usize;
         // println!("DBG Read sector {}", phy_sector);
-        read_n_sectors(self.device.as_mut(), phy_sector, factor, buf)
+        self.device.read_sectors(phy_sector, factor, buf)
+    }
+
+    fn read_sectors(&mut self, sector: u64, count: usize, buf: &mut [u8]) -> io::Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let phy_sector = match self.virtual_to_physical(sector + count as u64 - 1) {
+            Some(_) => self.partition.start + sector * self.factor(),
+            None => return ioerr!(InvalidInput, "virtual sector out of range"),
+        };
+        let factor = self.factor() as usize;
+        self.device.read_sectors(phy_sector, count * factor, buf)
     }
 
     fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
@@ -140,28 +133,188 @@ impl BlockDevice for BlockDevicePartition {
     }
 }
 
+/// Number of sectors `BlockDeviceCached::new()` keeps in memory.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Number of sectors read ahead of a sequential read by default.
+pub const DEFAULT_READAHEAD: usize = 32;
+
+/// Counters of the accesses to a `BlockDeviceCached`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of sector accesses served from memory.
+    pub hits: u64,
+    /// Number of sector accesses that read the sector from the device.
+    pub misses: u64,
+    /// Number of sectors dropped from the cache to make room for others.
+    pub evictions: u64,
+    /// Number of dirty sectors written back to the device.
+    pub writebacks: u64,
+    /// Number of sectors read ahead of sequential reads.
+    pub prefetched: u64,
+    /// Number of sectors currently cached.
+    pub cached: usize,
+    /// Maximum number of sectors cached at once.
+    pub capacity: usize,
+}
+
+/// A write-back cache of at most `capacity` sectors of a block device.
+///
+/// When the cache is full, a sector is evicted with the CLOCK algorithm: the
+/// slots are scanned in a circle from `hand`, clearing the `referenced` bit of
+/// the sectors accessed since the last scan, until one that was not accessed
+/// is found. Dirty sectors are written back to the device when evicted.
+///
+/// Runs of uncached sectors requested with `read_sectors()` are read from the
+/// device at once. When such a read continues the previous one, up to
+/// `readahead` following sectors are read with it so that a linear read of a
+/// file hits the cache.
 pub struct BlockDeviceCached {
     device: Box<dyn BlockDevice>,
-    cache: HashMap<u64, CacheEntry>,
+    entries: Vec<CacheEntry>,
+    /// Maps a sector number to its slot in `entries`.
+    index: HashMap<u64, usize>,
+    capacity: usize,
+    hand: usize,
+    readahead: usize,
+    /// Sector following the last one requested by `read_sectors()`.
+    next_sequential: Option<u64>,
+    stats: CacheStats,
 }
 
 impl fmt::Debug for BlockDeviceCached {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("BlockDeviceCached")
             .field("device", &self.device)
-            .field("cache", &self.cache)
+            .field("stats", &self.stats())
             .finish()
     }
 }
 
 impl BlockDeviceCached {
+    /// Returns a cache of `DEFAULT_CACHE_CAPACITY` sectors of `device`.
     pub fn new<T>(device: T) -> Self
     where
         T: BlockDevice + 'static,
     {
+        BlockDeviceCached::with_capacity(device, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Returns a cache of at most `capacity` sectors of `device`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn with_capacity<T>(device: T, capacity: usize) -> Self
+    where
+        T: BlockDevice + 'static,
+    {
+        assert!(capacity > 0, "cache capacity must be non-zero");
         Self {
             device: Box::new(device),
-            cache: HashMap::new(),
+            entries: Vec::new(),
+            index: HashMap::new(),
+            capacity,
+            hand: 0,
+            readahead: DEFAULT_READAHEAD,
+            next_sequential: None,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Returns the access statistics of the cache.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            cached: self.entries.len(),
+            capacity: self.capacity,
+            ..self.stats
+        }
+    }
+
+    /// Changes the maximum number of cached sectors to `capacity`. If the
+    /// cache holds more sectors than that, it is flushed and emptied.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        assert!(capacity > 0, "cache capacity must be non-zero");
+        if self.entries.len() > capacity {
+            self.flush()?;
+            self.entries.clear();
+            self.index.clear();
+            self.hand = 0;
+        }
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Sets the maximum number of sectors read ahead of a sequential read. 0
+    /// disables read-ahead. At most half of the cache is used for it.
+    pub fn set_readahead(&mut self, sectors: usize) {
+        self.readahead = sectors;
+    }
+
+    /// Returns the slot of the cached sector `sector`, reading it from the
+    /// disk and evicting another sector first if needed.
+    fn slot(&mut self, sector: u64) -> io::Result<usize> {
+        if let Some(&slot) = self.index.get(&sector) {
+            self.stats.hits += 1;
+            self.entries[slot].referenced = true;
+            return Ok(slot);
+        }
+
+        self.stats.misses += 1;
+        let mut sector_data = vec![0; self.device.sector_size() as usize];
+        self.device.read_sector(sector, &mut sector_data)?;
+        self.insert(sector, sector_data, true)
+    }
+
+    /// Caches `data` as the clean content of the uncached sector `sector` and
+    /// returns its slot, evicting another sector first if the cache is full.
+    fn insert(&mut self, sector: u64, data: Vec<u8>, referenced: bool) -> io::Result<usize> {
+        let cache_entry = CacheEntry {
+            sector,
+            data,
+            dirty: false,
+            referenced,
+        };
+        let slot = if self.entries.len() < self.capacity {
+            self.entries.push(cache_entry);
+            self.entries.len() - 1
+        } else {
+            let slot = self.evict()?;
+            self.entries[slot] = cache_entry;
+            slot
+        };
+        self.index.insert(sector, slot);
+        Ok(slot)
+    }
+
+    /// Selects a victim slot with the CLOCK algorithm, writes it back if it is
+    /// dirty and removes it from the index. Returns the freed slot.
+    fn evict(&mut self) -> io::Result<usize> {
+        loop {
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % self.entries.len();
+            let cache_entry = &mut self.entries[slot];
+            if cache_entry.referenced {
+                cache_entry.referenced = false;
+                continue;
+            }
+            if cache_entry.dirty {
+                self.device
+                    .write_sector(cache_entry.sector, &cache_entry.data)?;
+                cache_entry.dirty = false;
+                self.stats.writebacks += 1;
+            }
+            self.index.remove(&cache_entry.sector);
+            self.stats.evictions += 1;
+            return Ok(slot);
         }
     }
 
@@ -174,19 +327,11 @@ impl BlockDeviceCached {
     ///
     /// # Errors
     ///
-    /// Returns an error if there is an error reading the sector from the disk.
+    /// Returns an error if there is an error reading the sector from the disk
+    /// or writing back the sector evicted to make room for it.
     pub fn get_mut(&mut self, sector: u64) -> io::Result<&mut [u8]> {
-        let mut cache_entry = match self.cache.entry(sector) {
-            Entry::Occupied(entry) => entry.into_mut(),
-            Entry::Vacant(entry) => {
-                let mut sector_data = vec![0; self.device.sector_size() as usize];
-                self.device.read_sector(sector, &mut sector_data)?;
-                entry.insert(CacheEntry {
-                    data: sector_data,
-                    dirty: false,
-                })
-            }
-        };
+        let slot = self.slot(sector)?;
+        let cache_entry = &mut self.entries[slot];
         cache_entry.dirty = true;
         Ok(&mut cache_entry.data)
     }
@@ -196,20 +341,65 @@ impl BlockDeviceCached {
     ///
     /// # Errors
     ///
-    /// Returns an error if there is an error reading the sector from the disk.
+    /// Returns an error if there is an error reading the sector from the disk
+    /// or writing back the sector evicted to make room for it.
     pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
-        let cache_entry = match self.cache.entry(sector) {
-            Entry::Occupied(entry) => entry.into_mut(),
-            Entry::Vacant(entry) => {
-                let mut sector_data = vec![0; self.device.sector_size() as usize];
-                self.device.read_sector(sector, &mut sector_data)?;
-                entry.insert(CacheEntry {
-                    data: sector_data,
-                    dirty: false,
-                })
+        let slot = self.slot(sector)?;
+        Ok(&self.entries[slot].data)
+    }
+
+    /// Returns the number of uncached sectors following `sector`, up to the
+    /// read-ahead window.
+    fn readahead_len(&self, sector: u64) -> usize {
+        let window = cmp::min(self.readahead, self.capacity / 2) as u64;
+        (0..window)
+            .take_while(|i| !self.index.contains_key(&(sector + i)))
+            .count()
+    }
+
+    /// Reads the `count` uncached sectors starting at `sector` into `buf`
+    /// along with `ahead` more sectors, and caches all of them. If reading the
+    /// extra sectors fails, for instance past the end of the device, only the
+    /// requested ones are read.
+    fn fill(&mut self, sector: u64, count: usize, ahead: usize, buf: &mut [u8]) -> io::Result<()> {
+        let sector_size = self.sector_size() as usize;
+        let mut data = vec![0; (count + ahead) * sector_size];
+        let ahead = match self.device.read_sectors(sector, count + ahead, &mut data) {
+            Ok(read) if read == data.len() => ahead,
+            _ if ahead > 0 => {
+                data.truncate(count * sector_size);
+                self.device.read_sectors(sector, count, &mut data)?;
+                0
             }
+            Err(e) => return Err(e),
+            Ok(_) => return ioerr!(UnexpectedEof, "short read from device"),
         };
-        Ok(&cache_entry.data)
+        self.stats.misses += count as u64;
+        self.stats.prefetched += ahead as u64;
+
+        let len = cmp::min(buf.len(), count * sector_size);
+        buf[..len].copy_from_slice(&data[..len]);
+        for (i, sector_data) in data.chunks(sector_size).enumerate() {
+            self.insert(sector + i as u64, sector_data.to_vec(), i < count)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every dirty cached sector back to the disk and marks it clean.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error writing a sector to the disk.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for cache_entry in self.entries.iter_mut() {
+            if cache_entry.dirty {
+                self.device
+                    .write_sector(cache_entry.sector, &cache_entry.data)?;
+                cache_entry.dirty = false;
+                self.stats.writebacks += 1;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -225,6 +415,37 @@ impl BlockDevice for BlockDeviceCached {
         Ok(to_read)
     }
 
+    fn read_sectors(&mut self, n: u64, count: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let len = cmp::min(buf.len(), count * sector_size);
+        let count = (len + sector_size - 1) / sector_size;
+        let sequential = self.next_sequential == Some(n);
+        let mut i = 0;
+        while i < count {
+            let sector = n + i as u64;
+            let chunk = &mut buf[i * sector_size..cmp::min(len, (i + 1) * sector_size)];
+            if self.index.contains_key(&sector) {
+                let chunk_len = chunk.len();
+                chunk.copy_from_slice(&self.get(sector)?[..chunk_len]);
+                i += 1;
+                continue;
+            }
+            let run = (i..count)
+                .take_while(|&j| !self.index.contains_key(&(n + j as u64)))
+                .count();
+            let ahead = if sequential && i + run == count {
+                self.readahead_len(sector + run as u64)
+            } else {
+                0
+            };
+            let end = cmp::min(len, (i + run) * sector_size);
+            self.fill(sector, run, ahead, &mut buf[i * sector_size..end])?;
+            i += run;
+        }
+        self.next_sequential = Some(n + count as u64);
+        Ok(len)
+    }
+
     fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
         let to_write = cmp::min(self.sector_size() as usize, buf.len());
         let sector_data = self.get_mut(sector)?;
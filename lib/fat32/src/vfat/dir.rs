@@ -2,16 +2,20 @@ use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 
+use core::mem::size_of;
+use core::ops::Range;
+
 use shim::const_assert_size;
 use shim::ffi::OsStr;
 use shim::io;
-use shim::newioerr;
+use shim::{ioerr, newioerr};
 
 use crate::traits;
 use crate::util::VecExt;
 use crate::vfat::entry::EntryValue;
+use crate::vfat::metadata::{ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_LFN};
 use crate::vfat::{Attributes, Date, Metadata, Time, Timestamp};
-use crate::vfat::{Cluster, Entry, File, VFatHandle};
+use crate::vfat::{Cluster, Entry, File, Status, VFatHandle};
 
 #[derive(Debug, Clone)]
 pub struct Dir<HANDLE: VFatHandle> {
@@ -23,9 +27,12 @@ pub struct DirIter<HANDLE: VFatHandle> {
     dir: Dir<HANDLE>,
     pub raw_entries: Vec<VFatDirEntry>,
     pos: usize,
+    /// Position of the first raw entry (LFN or regular) of the last returned
+    /// entry.
+    start: usize,
 }
 
-fn regular_entry_name(regular_entry: &VFatRegularDirEntry) -> String {
+pub(crate) fn regular_entry_name(regular_entry: &VFatRegularDirEntry) -> String {
     let file_name_len = regular_entry
         .file_name
         .iter()
@@ -46,7 +53,7 @@ fn regular_entry_name(regular_entry: &VFatRegularDirEntry) -> String {
 }
 
 const MAX_LFN_ENTRIES: usize = 0x14;
-const LFN_ENTRY_LEN: usize = 13;
+pub(crate) const LFN_ENTRY_LEN: usize = 13;
 
 impl<HANDLE: VFatHandle> DirIter<HANDLE> {
     pub fn entry_name(&self, raw_entry: &VFatDirEntry, mut pos: usize) -> (String, usize) {
@@ -89,7 +96,7 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
     type Item = Entry<HANDLE>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut raw_entry = &self.raw_entries[self.pos];
+        let mut raw_entry = self.raw_entries.get(self.pos)?;
         loop {
             let unknown_entry = unsafe { raw_entry.unknown };
             match unknown_entry.id {
@@ -100,14 +107,21 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
                 }
             }
             self.pos += 1;
-            raw_entry = &self.raw_entries[self.pos];
+            raw_entry = self.raw_entries.get(self.pos)?;
         }
+        self.start = self.pos;
         let (name, new_pos) = self.entry_name(&raw_entry, self.pos);
         self.pos = new_pos;
         let mut raw_entry = &self.raw_entries[self.pos];
         let regular_entry = unsafe { raw_entry.regular };
         let first_cluster = regular_entry.first_cluster();
         let value = if regular_entry.attributes.directory() {
+            // A first cluster of 0 (in `..` entries) refers to the root directory.
+            let first_cluster = if first_cluster.raw() == 0 {
+                self.dir.vfat.lock(|vfat| vfat.rootdir_cluster())
+            } else {
+                first_cluster
+            };
             EntryValue::Dir(Dir {
                 vfat: self.dir.vfat.clone(),
                 first_cluster: first_cluster,
@@ -117,6 +131,8 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
                 self.dir.vfat.clone(),
                 first_cluster,
                 regular_entry.size,
+                self.dir.first_cluster,
+                self.pos,
             ))
         };
         let metadata = regular_entry.metadata();
@@ -148,9 +164,65 @@ pub struct VFatRegularDirEntry {
 }
 
 impl VFatRegularDirEntry {
+    pub fn new(
+        short_name: &[u8; 11],
+        attributes: Attributes,
+        first_cluster: Cluster,
+        ts: Timestamp,
+    ) -> Self {
+        let mut file_name = [0; 8];
+        let mut file_ext = [0; 3];
+        file_name.copy_from_slice(&short_name[..8]);
+        file_ext.copy_from_slice(&short_name[8..]);
+        let mut entry = VFatRegularDirEntry {
+            file_name,
+            file_ext,
+            attributes,
+            reserved_winnt: 0,
+            created_time_secs: 0,
+            created_time: ts.time,
+            created_date: ts.date,
+            accessed_date: ts.date,
+            first_cluster_hi: 0,
+            modified_time: ts.time,
+            modified_date: ts.date,
+            first_cluster_lo: 0,
+            size: 0,
+        };
+        entry.set_first_cluster(first_cluster);
+        entry
+    }
+    pub fn short_name(&self) -> [u8; 11] {
+        let mut short_name = [0; 11];
+        short_name[..8].copy_from_slice(&{ self.file_name });
+        short_name[8..].copy_from_slice(&{ self.file_ext });
+        short_name
+    }
+    pub fn set_short_name(&mut self, short_name: &[u8; 11]) {
+        self.file_name.copy_from_slice(&short_name[..8]);
+        self.file_ext.copy_from_slice(&short_name[8..]);
+    }
+    pub fn attributes(&self) -> Attributes {
+        self.attributes
+    }
     pub fn first_cluster(&self) -> Cluster {
         Cluster::from(self.first_cluster_lo as u32 | (self.first_cluster_hi as u32) << 16)
     }
+    pub fn set_first_cluster(&mut self, cluster: Cluster) {
+        self.first_cluster_lo = cluster.raw() as u16;
+        self.first_cluster_hi = (cluster.raw() >> 16) as u16;
+    }
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    pub fn set_size(&mut self, size: u32) {
+        self.size = size;
+    }
+    pub fn set_modified(&mut self, ts: Timestamp) {
+        self.modified_date = ts.date;
+        self.modified_time = ts.time;
+        self.accessed_date = ts.date;
+    }
     pub fn metadata(&self) -> Metadata {
         Metadata {
             attributes: self.attributes,
@@ -187,6 +259,25 @@ pub struct VFatLfnDirEntry {
 
 const_assert_size!(VFatLfnDirEntry, 32);
 
+impl VFatLfnDirEntry {
+    /// Returns the sequence number, including the 0x40 flag of the last
+    /// logical entry.
+    pub fn seq_num(&self) -> u8 {
+        self.seq_num
+    }
+    pub fn checksum(&self) -> u8 {
+        self.name_checksum
+    }
+    /// Returns the 13 UTF-16 code units of the name stored in this entry.
+    pub fn name_units(&self) -> [u16; LFN_ENTRY_LEN] {
+        let mut units = [0; LFN_ENTRY_LEN];
+        units[0..5].copy_from_slice(&{ self.name0 });
+        units[5..11].copy_from_slice(&{ self.name1 });
+        units[11..13].copy_from_slice(&{ self.name2 });
+        units
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug)]
 pub struct VFatUnknownDirEntry {
@@ -205,6 +296,173 @@ pub union VFatDirEntry {
     long_filename: VFatLfnDirEntry,
 }
 
+const DELETED_ENTRY_ID: u8 = 0xE5;
+
+impl VFatDirEntry {
+    /// Returns `true` if the entry is unused: either deleted or past the end
+    /// of the directory.
+    pub fn is_free(&self) -> bool {
+        let id = unsafe { self.unknown.id };
+        id == 0x00 || id == DELETED_ENTRY_ID
+    }
+    pub fn is_end(&self) -> bool {
+        unsafe { self.unknown.id == 0x00 }
+    }
+    pub fn mark_deleted(&mut self) {
+        self.unknown.id = DELETED_ENTRY_ID;
+    }
+    pub fn regular(&self) -> Option<VFatRegularDirEntry> {
+        if unsafe { self.unknown.attributes }.lfn() {
+            None
+        } else {
+            Some(unsafe { self.regular })
+        }
+    }
+    pub fn long_filename(&self) -> Option<VFatLfnDirEntry> {
+        if unsafe { self.unknown.attributes }.lfn() {
+            Some(unsafe { self.long_filename })
+        } else {
+            None
+        }
+    }
+    pub fn regular_mut(&mut self) -> &mut VFatRegularDirEntry {
+        unsafe { &mut self.regular }
+    }
+}
+
+impl From<VFatRegularDirEntry> for VFatDirEntry {
+    fn from(regular: VFatRegularDirEntry) -> Self {
+        VFatDirEntry { regular }
+    }
+}
+
+impl From<VFatLfnDirEntry> for VFatDirEntry {
+    fn from(long_filename: VFatLfnDirEntry) -> Self {
+        VFatDirEntry { long_filename }
+    }
+}
+
+/// Computes the checksum of an 8.3 short name stored in the LFN entries that
+/// precede its regular entry.
+pub fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &b| (sum >> 1 | sum << 7).wrapping_add(b))
+}
+
+/// Characters other than ASCII alphanumerics allowed in 8.3 short names.
+const SHORT_NAME_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+/// Characters never allowed in long file names.
+const LFN_INVALID: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+/// The maximum length of a long file name in UTF-16 code units.
+const LFN_MAX_LEN: usize = 255;
+
+fn is_short_name_char(b: u8) -> bool {
+    b.is_ascii_uppercase() || b.is_ascii_digit() || SHORT_NAME_SPECIAL.contains(&b)
+}
+
+/// Returns `true` if `name` may be used as the name of a new entry.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= LFN_MAX_LEN
+        && !name
+            .chars()
+            .any(|c| (c as u32) < 0x20 || LFN_INVALID.contains(&c))
+}
+
+/// Returns the 8.3 short name for `name` if `name` can be stored without
+/// LFN entries: an upper case base of at most 8 and an extension of at most 3
+/// valid characters.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.find('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || (ext.is_empty() && name.ends_with('.'))
+        || !base.bytes().chain(ext.bytes()).all(is_short_name_char)
+    {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short_name)
+}
+
+/// Generates a unique `BASIS~N.EXT` short name for `name` that does not
+/// collide with any of the short names in `taken`.
+fn generate_short_name(name: &str, taken: &[[u8; 11]]) -> io::Result<[u8; 11]> {
+    fn convert(s: &str) -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let b = c.to_ascii_uppercase() as u32;
+                if b < 0x80 && is_short_name_char(b as u8) {
+                    b as u8
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    }
+
+    let name = name.trim_start_matches('.');
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (convert(&name[..i]), convert(&name[i + 1..])),
+        None => (convert(name), Vec::new()),
+    };
+    let ext = &ext[..core::cmp::min(ext.len(), 3)];
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let base_len = core::cmp::min(base.len(), 8 - tail.len());
+        let mut short_name = [b' '; 11];
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(ext);
+        if !taken.contains(&short_name) {
+            return Ok(short_name);
+        }
+    }
+    ioerr!(AlreadyExists, "no unique short name available")
+}
+
+/// Builds the LFN entries storing `name` for the short name with checksum
+/// `checksum`, in the order they are stored on disk.
+fn lfn_entries(name: &str, checksum: u8) -> Vec<VFatDirEntry> {
+    let mut name_u16: Vec<u16> = name.encode_utf16().collect();
+    let count = (name_u16.len() + LFN_ENTRY_LEN - 1) / LFN_ENTRY_LEN;
+    if name_u16.len() % LFN_ENTRY_LEN != 0 {
+        name_u16.push(0x0000);
+    }
+    name_u16.resize(count * LFN_ENTRY_LEN, 0xffff);
+
+    let mut entries = Vec::with_capacity(count);
+    for seq_num in (1..=count).rev() {
+        let raw_name = &name_u16[(seq_num - 1) * LFN_ENTRY_LEN..seq_num * LFN_ENTRY_LEN];
+        let (mut name0, mut name1, mut name2) = ([0; 5], [0; 6], [0; 2]);
+        name0.copy_from_slice(&raw_name[0..5]);
+        name1.copy_from_slice(&raw_name[5..11]);
+        name2.copy_from_slice(&raw_name[11..13]);
+        let lfn_entry = VFatLfnDirEntry {
+            seq_num: seq_num as u8 | if seq_num == count { 0x40 } else { 0x00 },
+            name0,
+            attributes: Attributes::new(ATTR_LFN),
+            entry_type: 0,
+            name_checksum: checksum,
+            name1,
+            zeroes: 0,
+            name2,
+        };
+        entries.push(lfn_entry.into());
+    }
+    entries
+}
+
 use core::fmt;
 use core::fmt::Debug;
 
@@ -222,6 +480,259 @@ impl Debug for VFatDirEntry {
 }
 
 impl<HANDLE: VFatHandle> Dir<HANDLE> {
+    /// Finds the entry named `name` like `find()`. Also returns the range of
+    /// raw entry positions occupied by the entry (its LFN entries followed by
+    /// its regular entry) and a copy of its regular entry.
+    fn find_slots(
+        &self,
+        name: &str,
+    ) -> io::Result<(Entry<HANDLE>, Range<usize>, VFatRegularDirEntry)> {
+        use traits::{Dir, Entry};
+        let mut entries = self.entries()?;
+        while let Some(entry) = entries.next() {
+            if entry.name().eq_ignore_ascii_case(name) {
+                let regular_entry = unsafe { entries.raw_entries[entries.pos - 1].regular };
+                return Ok((entry, entries.start..entries.pos, regular_entry));
+            }
+        }
+        ioerr!(NotFound, "file name not found")
+    }
+
+    /// Writes `new_entries` into a run of free slots of this directory,
+    /// growing the directory's cluster chain if there is no such run. Returns
+    /// the position of the last written entry.
+    fn insert_raw_entries(&self, new_entries: &[VFatDirEntry]) -> io::Result<usize> {
+        use traits::Dir;
+        let raw_entries = self.entries()?.raw_entries;
+        let mut start = 0;
+        for (i, raw_entry) in raw_entries.iter().enumerate() {
+            if raw_entry.is_end() || i - start >= new_entries.len() {
+                break;
+            }
+            if !raw_entry.is_free() {
+                start = i + 1;
+            }
+        }
+        let end = start + new_entries.len();
+        self.vfat.lock(|vfat| -> io::Result<usize> {
+            let entries_per_cluster = vfat.cluster_size() as usize / size_of::<VFatDirEntry>();
+            let mut len = raw_entries.len();
+            if end > len {
+                if vfat.is_fixed_rootdir(self.first_cluster) {
+                    return ioerr!(Other, "root directory is full");
+                }
+                let mut last = self.first_cluster;
+                while let Status::Data(next) = vfat.fat_entry(last)?.status() {
+                    last = next;
+                }
+                while end > len {
+                    last = vfat.alloc_cluster(Some(last))?;
+                    vfat.zero_cluster(last)?;
+                    len += entries_per_cluster;
+                }
+            }
+            for (i, new_entry) in new_entries.iter().enumerate() {
+                *vfat.dir_entry_mut(self.first_cluster, start + i)? = *new_entry;
+            }
+            Ok(start + new_entries.len() - 1)
+        })
+    }
+
+    /// Adds an entry named `name` for `regular_entry` to this directory,
+    /// generating its short name and LFN entries. Returns the position of the
+    /// regular entry.
+    ///
+    /// Fails with `AlreadyExists` if an entry is named `name`, or if `name` is
+    /// a valid short name that another entry has, such as the generated short
+    /// name of a long name. The entry at the positions `renamed`, if any, is
+    /// not checked against `name`: it is the entry being renamed to `name`.
+    fn insert_entry(
+        &self,
+        name: &str,
+        mut regular_entry: VFatRegularDirEntry,
+        renamed: Option<&Range<usize>>,
+    ) -> io::Result<usize> {
+        use traits::{Dir, Entry};
+        if !is_valid_name(name) {
+            return ioerr!(InvalidInput, "invalid file name");
+        }
+        let mut entries = self.entries()?;
+        while let Some(entry) = entries.next() {
+            let is_renamed = renamed.map_or(false, |slots| slots.start == entries.start);
+            if !is_renamed && entry.name().eq_ignore_ascii_case(name) {
+                return ioerr!(AlreadyExists, "file name already exists");
+            }
+        }
+        let taken: Vec<[u8; 11]> = entries
+            .raw_entries
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !renamed.map_or(false, |slots| slots.contains(&i)))
+            .map(|(_, e)| e)
+            .filter(|e| !e.is_free() && !unsafe { e.unknown }.attributes.lfn())
+            .map(|e| unsafe { e.regular }.short_name())
+            .collect();
+        let mut new_entries = Vec::new();
+        let short_name = match exact_short_name(name) {
+            Some(short_name) if taken.contains(&short_name) => {
+                return ioerr!(AlreadyExists, "file name already exists");
+            }
+            Some(short_name) => short_name,
+            None => {
+                let short_name = generate_short_name(name, &taken)?;
+                new_entries = lfn_entries(name, lfn_checksum(&short_name));
+                short_name
+            }
+        };
+        regular_entry.set_short_name(&short_name);
+        new_entries.push(regular_entry.into());
+        self.insert_raw_entries(&new_entries)
+    }
+
+    /// Marks the raw entries at positions `slots` as deleted.
+    fn delete_slots(&self, slots: Range<usize>) -> io::Result<()> {
+        self.vfat.lock(|vfat| -> io::Result<()> {
+            for i in slots {
+                vfat.dir_entry_mut(self.first_cluster, i)?.mark_deleted();
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the cluster stored in `..` entries of subdirectories of this
+    /// directory: 0 for the root directory.
+    fn parent_ref(&self) -> Cluster {
+        if self.first_cluster == self.vfat.lock(|vfat| vfat.rootdir_cluster()) {
+            Cluster::from(0)
+        } else {
+            self.first_cluster
+        }
+    }
+
+    /// Creates an empty regular file named `name` in this directory.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a valid file name, an error of `InvalidInput` is
+    /// returned. If an entry named `name` already exists, an error of
+    /// `AlreadyExists` is returned.
+    pub fn create_file(&self, name: &str) -> io::Result<File<HANDLE>> {
+        let now = self.vfat.lock(|vfat| vfat.now());
+        let regular_entry = VFatRegularDirEntry::new(
+            &[b' '; 11],
+            Attributes::new(ATTR_ARCHIVE),
+            Cluster::from(0),
+            now,
+        );
+        let index = self.insert_entry(name, regular_entry, None)?;
+        self.vfat.lock(|vfat| vfat.flush())?;
+        Ok(File::new(
+            self.vfat.clone(),
+            Cluster::from(0),
+            0,
+            self.first_cluster,
+            index,
+        ))
+    }
+
+    /// Creates an empty directory named `name` in this directory.
+    ///
+    /// # Errors
+    ///
+    /// The error conditions are the same as for `create_file()`.
+    pub fn create_dir(&self, name: &str) -> io::Result<Dir<HANDLE>> {
+        let parent = self.parent_ref();
+        let (cluster, now) = self.vfat.lock(|vfat| -> io::Result<(Cluster, Timestamp)> {
+            let now = vfat.now();
+            let cluster = vfat.alloc_cluster(None)?;
+            vfat.zero_cluster(cluster)?;
+            let attributes = Attributes::new(ATTR_DIRECTORY);
+            *vfat.dir_entry_mut(cluster, 0)? =
+                VFatRegularDirEntry::new(b".          ", attributes, cluster, now).into();
+            *vfat.dir_entry_mut(cluster, 1)? =
+                VFatRegularDirEntry::new(b"..         ", attributes, parent, now).into();
+            Ok((cluster, now))
+        })?;
+        let regular_entry =
+            VFatRegularDirEntry::new(&[b' '; 11], Attributes::new(ATTR_DIRECTORY), cluster, now);
+        if let Err(e) = self.insert_entry(name, regular_entry, None) {
+            self.vfat.lock(|vfat| vfat.free_chain(cluster))?;
+            return Err(e);
+        }
+        self.vfat.lock(|vfat| vfat.flush())?;
+        Ok(Dir {
+            vfat: self.vfat.clone(),
+            first_cluster: cluster,
+        })
+    }
+
+    /// Removes the entry named `name` from this directory and frees its
+    /// clusters.
+    ///
+    /// # Errors
+    ///
+    /// If no entry named `name` exists, an error of `NotFound` is returned.
+    /// If the entry is a directory that is not empty, or `name` is `.` or
+    /// `..`, an error of `Other` is returned.
+    pub fn remove(&self, name: &str) -> io::Result<()> {
+        use traits::{Dir, Entry};
+        if name == "." || name == ".." {
+            return ioerr!(Other, "cannot remove '.' or '..'");
+        }
+        let (entry, slots, regular_entry) = self.find_slots(name)?;
+        if let Some(dir) = entry.as_dir() {
+            if dir.entries()?.any(|e| e.name() != "." && e.name() != "..") {
+                return ioerr!(Other, "directory not empty");
+            }
+        }
+        drop(entry);
+        self.delete_slots(slots)?;
+        let first_cluster = regular_entry.first_cluster();
+        self.vfat.lock(|vfat| -> io::Result<()> {
+            if first_cluster.raw() != 0 {
+                vfat.free_chain(first_cluster)?;
+            }
+            vfat.flush()
+        })
+    }
+
+    /// Moves the entry named `name` to the directory `dest` under the name
+    /// `new_name`. `dest` may be this directory.
+    ///
+    /// # Errors
+    ///
+    /// If no entry named `name` exists, an error of `NotFound` is returned.
+    /// If an entry named `new_name` already exists in `dest`, an error of
+    /// `AlreadyExists` is returned. If `new_name` is not a valid file name, an
+    /// error of `InvalidInput` is returned.
+    pub fn rename(&self, name: &str, dest: &Dir<HANDLE>, new_name: &str) -> io::Result<()> {
+        use traits::Entry;
+        if name == "." || name == ".." {
+            return ioerr!(InvalidInput, "cannot rename '.' or '..'");
+        }
+        let (entry, slots, regular_entry) = self.find_slots(name)?;
+        let same_dir = self.first_cluster == dest.first_cluster;
+        // Within a directory, the old name and short name are not reported as
+        // already existing, so that only the case of the name may change or
+        // the entry may take its own short name. The old slots are only freed
+        // once the new ones are written, so that the entry is never lost.
+        let renamed = if same_dir { Some(&slots) } else { None };
+        dest.insert_entry(new_name, regular_entry, renamed)?;
+        self.delete_slots(slots)?;
+        if let Some(dir) = entry.as_dir() {
+            if !same_dir {
+                let (_, parent_slots, _) = dir.find_slots("..")?;
+                let parent = dest.parent_ref();
+                self.vfat.lock(|vfat| -> io::Result<()> {
+                    vfat.update_dir_entry(dir.first_cluster, parent_slots.end - 1, |entry| {
+                        entry.set_first_cluster(parent)
+                    })
+                })?;
+            }
+        }
+        self.vfat.lock(|vfat| vfat.flush())
+    }
+
     /// Finds the entry named `name` in `self` and returns it. Comparison is
     /// case-insensitive.
     ///
@@ -267,6 +778,7 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
             raw_entries: unsafe { data.cast() },
             // raw_entries,
             pos: 0,
+            start: 0,
         })
     }
 }
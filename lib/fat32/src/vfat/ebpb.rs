@@ -3,7 +3,7 @@ use core::fmt;
 use shim::const_assert_size;
 
 use crate::traits::BlockDevice;
-use crate::vfat::Error;
+use crate::vfat::{Error, FatType};
 
 #[derive(Clone, Copy)]
 #[repr(C, packed)]
@@ -42,8 +42,13 @@ pub struct BiosParameterBlock {
 const_assert_size!(BiosParameterBlock, 512);
 
 impl BiosParameterBlock {
-    /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
-    /// device `device`.
+    /// Reads the extended BIOS parameter block from sector `sector` of device
+    /// `device`.
+    ///
+    /// FAT12 and FAT16 boot sectors, recognized by a non-zero 16-bit sectors
+    /// per FAT count, store their extended fields right after the DOS 3.31
+    /// BPB. Those fields are moved to their FAT32 location and the FAT32 only
+    /// fields are zeroed, so `rootdir_cluster` is 0 for FAT12 and FAT16.
     ///
     /// # Errors
     ///
@@ -51,13 +56,88 @@ impl BiosParameterBlock {
     pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<BiosParameterBlock, Error> {
         let mut sector_data = vec![0; device.sector_size() as usize];
         device.read_sector(sector, &mut sector_data)?;
-        let ebpb = unsafe { *{ sector_data.as_ptr() as *const BiosParameterBlock } };
+        let mut ebpb = unsafe { *{ sector_data.as_ptr() as *const BiosParameterBlock } };
+        if ebpb.sectors_per_fat_16 != 0 {
+            ebpb.from_fat16_layout(&sector_data);
+        }
         if ebpb.signature != 0x28 && ebpb.signature != 0x29 {
             return Err(Error::BadSignature);
         }
         Ok(ebpb)
     }
 
+    /// Returns an EBPB of a `fat_type` file system with the given volume ID
+    /// and label. The geometry fields are zeroed and must be set by the
+    /// caller.
+    pub(crate) fn new(
+        fat_type: FatType,
+        volume_id: u32,
+        volume_label: [u8; 11],
+    ) -> BiosParameterBlock {
+        let mut ebpb: BiosParameterBlock = unsafe { core::mem::zeroed() };
+        ebpb.jmp_short_xx_nop = match fat_type {
+            FatType::Fat32 => [0xEB, 0x58, 0x90],
+            _ => [0xEB, 0x3C, 0x90],
+        };
+        ebpb._oem_id = *b"CS140E  ";
+        ebpb.fat_id = 0xF8;
+        ebpb.drive_num = 0x80;
+        ebpb.signature = 0x29;
+        ebpb.volume_id = volume_id;
+        ebpb._volume_label = volume_label;
+        ebpb._system_id = match fat_type {
+            FatType::Fat12 => *b"FAT12   ",
+            FatType::Fat16 => *b"FAT16   ",
+            FatType::Fat32 => *b"FAT32   ",
+        };
+        ebpb.boot_part_signature = [0x55, 0xAA];
+        ebpb
+    }
+
+    fn from_fat16_layout(&mut self, sector_data: &[u8]) {
+        let mut volume_id = [0; 4];
+        volume_id.copy_from_slice(&sector_data[39..43]);
+        self.sectors_per_fat_32 = 0;
+        self.flags = 0;
+        self.fat_version = [0; 2];
+        self.rootdir_cluster = 0;
+        self.fsinfo_sector = 0;
+        self.boot_sector_backup_sector = 0;
+        self.reserved = [0; 12];
+        self.drive_num = sector_data[36];
+        self.flags_winnt = sector_data[37];
+        self.signature = sector_data[38];
+        self.volume_id = u32::from_le_bytes(volume_id);
+        self._volume_label.copy_from_slice(&sector_data[43..54]);
+        self._system_id.copy_from_slice(&sector_data[54..62]);
+        self.boot_code.copy_from_slice(&sector_data[62..482]);
+    }
+
+    /// Returns the boot sector of a FAT12 or FAT16 file system holding this
+    /// EBPB: the extended fields are moved back right after the DOS 3.31 BPB,
+    /// as read by `from()`.
+    pub(crate) fn to_fat16_layout(&self) -> [u8; 512] {
+        let mut sector_data = [0; 512];
+        let bytes = unsafe { &*(self as *const BiosParameterBlock as *const [u8; 512]) };
+        sector_data[..36].copy_from_slice(&bytes[..36]);
+        sector_data[36] = self.drive_num;
+        sector_data[37] = self.flags_winnt;
+        sector_data[38] = self.signature;
+        sector_data[39..43].copy_from_slice(&{ self.volume_id }.to_le_bytes());
+        sector_data[43..54].copy_from_slice(&self._volume_label);
+        sector_data[54..62].copy_from_slice(&self._system_id);
+        sector_data[62..482].copy_from_slice(&self.boot_code);
+        sector_data[510..].copy_from_slice(&self.boot_part_signature);
+        sector_data
+    }
+
+    /// Returns the number of sectors of the fixed size root directory region
+    /// of FAT12 and FAT16 file systems. Always 0 for FAT32.
+    pub fn rootdir_sectors(&self) -> u32 {
+        let bytes_per_sector = self.bytes_per_sector as u32;
+        (self.max_num_dir as u32 * 32 + bytes_per_sector - 1) / bytes_per_sector
+    }
+
     pub fn logical_sectors(&self) -> u32 {
         if self.logical_sectors_16 == 0 {
             self.logical_sectors_32
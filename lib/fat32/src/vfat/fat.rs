@@ -19,13 +19,59 @@ pub enum Status {
     Eoc(u32),
 }
 
-#[repr(C, packed)]
-pub struct FatEntry(pub u32);
+/// The FAT variant of a file system, determined by its number of data
+/// clusters.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Returns the FAT type of a file system with `clusters` data clusters.
+    pub fn from_clusters(clusters: u32) -> FatType {
+        if clusters < 4085 {
+            FatType::Fat12
+        } else if clusters < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    /// Returns the width of a FAT entry in bits.
+    pub fn entry_bits(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 12,
+            FatType::Fat16 => 16,
+            FatType::Fat32 => 32,
+        }
+    }
+
+    /// Returns the mask of the bits of a FAT entry holding its value.
+    pub fn entry_mask(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0x0fff,
+            FatType::Fat16 => 0xffff,
+            FatType::Fat32 => 0x0fff_ffff,
+        }
+    }
+}
+
+/// A FAT entry value along with the type of the FAT it was read from.
+pub struct FatEntry(pub u32, pub FatType);
 
 impl FatEntry {
     /// Returns the `Status` of the FAT entry `self`.
     pub fn status(&self) -> Status {
-        let value = self.0 & 0x0fff_ffff;
+        let mask = self.1.entry_mask();
+        let mut value = self.0 & mask;
+        // Values in the reserved range at the top of FAT12 and FAT16 entries
+        // (e.g. 0xFF8 to 0xFFF for end of chain) map to the same FAT32 ones.
+        if value >= mask & 0x0fff_fff0 {
+            value |= 0x0fff_fff0;
+        }
         match value {
             0x0000_0000 => Status::Free,
             0x0000_0001 => Status::Reserved,
@@ -43,6 +89,7 @@ impl fmt::Debug for FatEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FatEntry")
             .field("value", &{ self.0 })
+            .field("fat_type", &self.1)
             .field("status", &self.status())
             .finish()
     }
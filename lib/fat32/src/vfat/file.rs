@@ -5,7 +5,7 @@ use shim::{ioerr, newioerr};
 
 use crate::traits;
 // use crate::util::print_hex;
-use crate::vfat::{Chain, Cluster, Metadata, VFatHandle};
+use crate::vfat::{Chain, Cluster, Metadata, Status, VFatHandle};
 
 #[derive(Debug)]
 pub struct File<HANDLE: VFatHandle> {
@@ -13,13 +13,27 @@ pub struct File<HANDLE: VFatHandle> {
     cluster_size: u64,
     pub first_cluster: Cluster,
     pub current_cluster: Cluster,
+    /// Index in the cluster chain of `current_cluster`.
+    current_index: u64,
     // pub chain: Chain<HANDLE>,
     pub size: u32,
     pub pos: u64,
+    /// First cluster of the directory holding the entry of this file.
+    dir_cluster: Cluster,
+    /// Position of the regular entry of this file in its directory.
+    dir_index: usize,
+    /// Whether the file has been written since the last `sync()`.
+    dirty: bool,
 }
 
 impl<HANDLE: VFatHandle> File<HANDLE> {
-    pub fn new(vfat: HANDLE, first_cluster: Cluster, size: u32) -> File<HANDLE> {
+    pub fn new(
+        vfat: HANDLE,
+        first_cluster: Cluster,
+        size: u32,
+        dir_cluster: Cluster,
+        dir_index: usize,
+    ) -> File<HANDLE> {
         // let chain = vfat.chain(first_cluster);
         let cluster_size = vfat.lock(|vfat| vfat.cluster_size());
         File {
@@ -27,17 +41,62 @@ impl<HANDLE: VFatHandle> File<HANDLE> {
             cluster_size,
             first_cluster,
             current_cluster: first_cluster,
+            current_index: 0,
             // chain,
             size,
             pos: 0,
+            dir_cluster,
+            dir_index,
+            dirty: false,
         }
     }
+
+    /// Moves `current_cluster` to the cluster holding the byte at `pos`. If
+    /// `extend` is `true`, clusters are appended to the chain as needed.
+    /// Otherwise an `InvalidInput` error is returned when the chain ends
+    /// before `pos`.
+    fn seek_cluster(&mut self, extend: bool) -> io::Result<()> {
+        let cluster_index = self.pos / self.cluster_size;
+        if cluster_index < self.current_index {
+            self.current_cluster = self.first_cluster;
+            self.current_index = 0;
+        }
+        while self.current_index < cluster_index {
+            let current = self.current_cluster;
+            self.current_cluster = self.vfat.lock(|vfat| -> io::Result<Cluster> {
+                match vfat.fat_entry(current)?.status() {
+                    Status::Data(next) => Ok(next),
+                    Status::Eoc(_) if extend => vfat.alloc_cluster(Some(current)),
+                    Status::Eoc(_) => ioerr!(InvalidInput, "position past the end of file"),
+                    _ => ioerr!(InvalidData, "Invalid chain fat entry"),
+                }
+            })?;
+            self.current_index += 1;
+        }
+        Ok(())
+    }
 }
 
 // FIXME: Implement `traits::File` (and its supertraits) for `File`.
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
+    /// Writes the size, first cluster and modification time of the file to
+    /// its directory entry, then flushes every dirty cached sector to disk.
     fn sync(&mut self) -> io::Result<()> {
-        // TODO
+        let dirty = self.dirty;
+        let (first_cluster, size) = (self.first_cluster, self.size);
+        let (dir_cluster, dir_index) = (self.dir_cluster, self.dir_index);
+        self.vfat.lock(|vfat| -> io::Result<()> {
+            if dirty {
+                let now = vfat.now();
+                vfat.update_dir_entry(dir_cluster, dir_index, |entry| {
+                    entry.set_first_cluster(first_cluster);
+                    entry.set_size(size);
+                    entry.set_modified(now);
+                })?;
+            }
+            vfat.flush()
+        })?;
+        self.dirty = false;
         Ok(())
     }
 
@@ -46,6 +105,16 @@ impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
     }
 }
 
+impl<HANDLE: VFatHandle> Drop for File<HANDLE> {
+    fn drop(&mut self) {
+        use traits::File;
+
+        if self.dirty {
+            let _ = self.sync();
+        }
+    }
+}
+
 impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     /// Seek to offset `pos` in the file.
     ///
@@ -73,19 +142,16 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
         };
         self.pos = pos as u64;
 
-        let cluster_index = self.pos / self.cluster_size;
-        let cluster_offset = self.pos % self.cluster_size;
-        let mut chain = self.vfat.chain(self.first_cluster);
-        self.current_cluster = chain
-            .skip(cluster_index as usize)
-            .nth(0)
-            .ok_or(newioerr!(InvalidInput, "position past the end of file"))??;
-
+        // The cluster holding the new position is looked up lazily by the next read or write,
+        // since a position at the end of the file may not have a cluster yet.
         Ok(self.pos)
     }
 }
 
 impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
+    /// Reads up to `buf.len()` bytes from the current position. Whole
+    /// clusters are read straight into `buf`, contiguous clusters with a
+    /// single device read; only partial clusters go through a bounce buffer.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         use traits::File;
 
@@ -94,45 +160,74 @@ impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
         } else if self.pos >= self.size as u64 {
             return ioerr!(InvalidInput, "read past the end of file");
         }
-        let cluster_index = self.pos / self.cluster_size;
-        let cluster_offset = self.pos % self.cluster_size;
-
-        let cluster_data_len = if cluster_index == self.size as u64 / self.cluster_size {
-            self.size as u64 % self.cluster_size
-        } else {
-            self.cluster_size
-        };
-        let mut cluster_data = vec![0; cluster_data_len as usize];
-        self.vfat.lock(|vfat| -> io::Result<()> {
-            vfat.read_cluster(self.current_cluster, &mut cluster_data)?;
-            Ok(())
-        });
-        let len = core::cmp::min(buf.len() as u64, cluster_data_len - cluster_offset) as usize;
-        buf[..len]
-            .copy_from_slice(&cluster_data[cluster_offset as usize..cluster_offset as usize + len]);
-        self.pos += len as u64;
-
-        // If we just read until the end of the cluster, and it's not the last cluster, preload the
-        // next cluster index
-        if self.pos != self.size as u64
-            && cluster_offset as usize + len == cluster_data_len as usize
-        {
-            let mut chain = self.vfat.chain(self.current_cluster);
-            self.current_cluster = chain
-                .nth(1)
-                .ok_or(newioerr!(InvalidInput, "position past the end of file"))??;
+        let len = core::cmp::min(buf.len() as u64, self.size() - self.pos) as usize;
+        let mut read = 0;
+        while read < len {
+            self.seek_cluster(false)?;
+            let cluster_offset = (self.pos % self.cluster_size) as usize;
+            let cluster_size = self.cluster_size as usize;
+            let current_cluster = self.current_cluster;
+            let n = if cluster_offset == 0 && len - read >= cluster_size {
+                let clusters = (len - read) / cluster_size;
+                let dst = &mut buf[read..read + clusters * cluster_size];
+                self.vfat
+                    .lock(|vfat| vfat.read_cluster_run(current_cluster, clusters, dst))?
+            } else {
+                let mut cluster_data = vec![0; cluster_size];
+                self.vfat
+                    .lock(|vfat| vfat.read_cluster(current_cluster, &mut cluster_data))?;
+                let n = core::cmp::min(len - read, cluster_size - cluster_offset);
+                buf[read..read + n]
+                    .copy_from_slice(&cluster_data[cluster_offset..cluster_offset + n]);
+                n
+            };
+            if n == 0 {
+                return ioerr!(UnexpectedEof, "short read from device");
+            }
+            self.pos += n as u64;
+            read += n;
         }
-        Ok(len)
+        Ok(read)
     }
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
+    /// Writes `buf` at the current position, growing the file (and its
+    /// cluster chain) when writing past the end. At most one cluster worth of
+    /// data is written per call.
+    ///
+    /// The directory entry of the file is only updated on `sync()`, `flush()`
+    /// or when the file is dropped.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        // TODO
-        unimplemented!()
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos + buf.len() as u64 > core::u32::MAX as u64 {
+            return ioerr!(InvalidInput, "file size exceeds 4 GiB");
+        }
+        if self.first_cluster.raw() == 0 {
+            // Empty files have no cluster chain yet.
+            let first_cluster = self.vfat.lock(|vfat| vfat.alloc_cluster(None))?;
+            self.first_cluster = first_cluster;
+            self.current_cluster = first_cluster;
+            self.current_index = 0;
+            self.dirty = true;
+        }
+        self.seek_cluster(true)?;
+        let cluster_offset = (self.pos % self.cluster_size) as usize;
+        let current_cluster = self.current_cluster;
+        let len = self
+            .vfat
+            .lock(|vfat| vfat.write_cluster(current_cluster, cluster_offset, buf))?;
+        self.pos += len as u64;
+        if self.pos > self.size as u64 {
+            self.size = self.pos as u32;
+        }
+        self.dirty = true;
+        Ok(len)
     }
+
     fn flush(&mut self) -> io::Result<()> {
-        // TODO
-        unimplemented!()
+        traits::File::sync(self)
     }
 }
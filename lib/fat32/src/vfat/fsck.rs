@@ -0,0 +1,507 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+
+use crate::util::VecExt;
+use crate::vfat::dir::{lfn_checksum, regular_entry_name};
+use crate::vfat::dir::{VFatDirEntry, VFatLfnDirEntry, VFatRegularDirEntry};
+use crate::vfat::vfat::FAT_EOC;
+use crate::vfat::{Cluster, Error, Status, VFat, VFatHandle, FSINFO_UNKNOWN};
+
+/// An inconsistency found by `VFat::check()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// Cluster `cluster` is part of the chains of both `first` and `second`.
+    /// Repaired by truncating the chain of `second` before `cluster`.
+    CrossLinked {
+        cluster: u32,
+        first: String,
+        second: String,
+    },
+    /// The chain of `path` is broken at cluster `cluster`: it is out of range,
+    /// its FAT entry is free or bad, or the chain loops back to it. Repaired
+    /// by ending the chain at its last valid cluster.
+    BrokenChain { path: String, cluster: u32 },
+    /// A chain of `clusters` allocated clusters starting at `start` is not
+    /// referenced by any directory entry. Repaired by freeing the chain.
+    LostChain { start: u32, clusters: u32 },
+    /// The file `path` of `size` bytes has a chain of `clusters` clusters.
+    /// Repaired by freeing the clusters past the size, or by shrinking the
+    /// size to the chain length.
+    SizeMismatch {
+        path: String,
+        size: u32,
+        clusters: u32,
+    },
+    /// The LFN entries preceding the entry at position `index` of directory
+    /// `dir` are out of sequence or do not match the checksum of its short
+    /// name. Repaired by deleting the LFN entries, leaving the short name.
+    BadLfn { dir: String, index: usize },
+    /// `entries` entries of FAT copy `fat` differ from the first FAT.
+    /// Repaired by copying the first FAT.
+    FatMismatch { fat: u8, entries: u32 },
+    /// The FSInfo free cluster count is `recorded` but `actual` clusters are
+    /// free. Repaired by storing the actual count.
+    FreeCount { recorded: u32, actual: u32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::CrossLinked {
+                cluster,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} and {} are cross-linked at cluster {}",
+                first, second, cluster
+            ),
+            Problem::BrokenChain { path, cluster } => {
+                write!(f, "{}: broken cluster chain at cluster {}", path, cluster)
+            }
+            Problem::LostChain { start, clusters } => write!(
+                f,
+                "lost chain of {} clusters starting at cluster {}",
+                clusters, start
+            ),
+            Problem::SizeMismatch {
+                path,
+                size,
+                clusters,
+            } => write!(
+                f,
+                "{}: size of {} bytes does not match chain of {} clusters",
+                path, size, clusters
+            ),
+            Problem::BadLfn { dir, index } => {
+                write!(f, "{}: bad long file name before entry {}", dir, index)
+            }
+            Problem::FatMismatch { fat, entries } => write!(
+                f,
+                "FAT copy {} differs from FAT 0 in {} entries",
+                fat, entries
+            ),
+            Problem::FreeCount { recorded, actual } => write!(
+                f,
+                "FSInfo free cluster count is {} but {} clusters are free",
+                recorded, actual
+            ),
+        }
+    }
+}
+
+/// The chain of a directory entry as collected by `Checker::claim_chain()`.
+struct ClaimedChain {
+    clusters: Vec<Cluster>,
+    /// Whether the first cluster itself is invalid, so the entry should no
+    /// longer refer to any chain.
+    clear_first: bool,
+}
+
+struct Checker<'a, HANDLE: VFatHandle> {
+    vfat: &'a mut VFat<HANDLE>,
+    repair: bool,
+    /// Index in `paths` of the entry owning each cluster.
+    owners: Vec<Option<usize>>,
+    paths: Vec<String>,
+    problems: Vec<Problem>,
+}
+
+impl<'a, HANDLE: VFatHandle> Checker<'a, HANDLE> {
+    fn is_data_cluster(&self, cluster: Cluster) -> bool {
+        cluster.raw() >= 2 && (cluster.raw() as usize) < self.owners.len()
+    }
+
+    fn check_fat_copies(&mut self) -> Result<(), Error> {
+        let end = self.owners.len() as u32;
+        for fat in 1..self.vfat.fats() {
+            let mut mismatched = Vec::new();
+            for raw in 2..end {
+                let cluster = Cluster::from(raw);
+                if self.vfat.fat_entry_in(fat, cluster)?.0 != self.vfat.fat_entry(cluster)?.0 {
+                    mismatched.push(cluster);
+                }
+            }
+            if mismatched.is_empty() {
+                continue;
+            }
+            self.problems.push(Problem::FatMismatch {
+                fat,
+                entries: mismatched.len() as u32,
+            });
+            if self.repair {
+                for cluster in mismatched {
+                    let value = self.vfat.fat_entry(cluster)?.0;
+                    self.vfat.set_fat_entry(cluster, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the clusters of the chain starting at `first` as owned by
+    /// `path`, reporting (and repairing) broken and cross-linked chains.
+    fn claim_chain(&mut self, first: Cluster, path: &str) -> Result<ClaimedChain, Error> {
+        let id = self.paths.len();
+        self.paths.push(String::from(path));
+        let mut clusters: Vec<Cluster> = Vec::new();
+        let mut cluster = first;
+        loop {
+            let problem = if !self.is_data_cluster(cluster) {
+                Some(Problem::BrokenChain {
+                    path: String::from(path),
+                    cluster: cluster.raw(),
+                })
+            } else {
+                match self.owners[cluster.raw() as usize] {
+                    Some(owner) if owner == id => Some(Problem::BrokenChain {
+                        path: String::from(path),
+                        cluster: cluster.raw(),
+                    }),
+                    Some(owner) => Some(Problem::CrossLinked {
+                        cluster: cluster.raw(),
+                        first: self.paths[owner].clone(),
+                        second: String::from(path),
+                    }),
+                    None => None,
+                }
+            };
+            if let Some(problem) = problem {
+                self.problems.push(problem);
+                if self.repair {
+                    if let Some(&last) = clusters.last() {
+                        self.vfat.set_fat_entry(last, FAT_EOC)?;
+                    }
+                }
+                break;
+            }
+            self.owners[cluster.raw() as usize] = Some(id);
+            clusters.push(cluster);
+            match self.vfat.fat_entry(cluster)?.status() {
+                Status::Data(next) => cluster = next,
+                Status::Eoc(_) => break,
+                _ => {
+                    self.problems.push(Problem::BrokenChain {
+                        path: String::from(path),
+                        cluster: cluster.raw(),
+                    });
+                    if self.repair {
+                        self.vfat.set_fat_entry(cluster, FAT_EOC)?;
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(ClaimedChain {
+            clear_first: clusters.is_empty(),
+            clusters,
+        })
+    }
+
+    /// Checks the directory starting at `first_cluster` with `clusters`
+    /// clusters, or the fixed size root directory region if `clusters` is
+    /// `None`.
+    fn check_dir(
+        &mut self,
+        first_cluster: Cluster,
+        clusters: Option<&[Cluster]>,
+        path: &str,
+    ) -> Result<(), Error> {
+        let mut data = Vec::new();
+        match clusters {
+            Some(clusters) => {
+                let mut cluster_data = vec![0; self.vfat.cluster_size() as usize];
+                for &cluster in clusters {
+                    self.vfat.read_cluster(cluster, &mut cluster_data)?;
+                    data.extend_from_slice(&cluster_data);
+                }
+            }
+            None => {
+                self.vfat.read_chain(first_cluster, &mut data)?;
+            }
+        }
+        data.truncate(data.len() / size_of::<VFatDirEntry>() * size_of::<VFatDirEntry>());
+        let entries: Vec<VFatDirEntry> = unsafe { data.cast() };
+
+        let mut lfn_run = Vec::new();
+        for (index, entry) in entries.iter().enumerate() {
+            if entry.is_end() {
+                break;
+            }
+            if entry.is_free() {
+                self.check_orphan_lfn(first_cluster, path, &mut lfn_run, index)?;
+                continue;
+            }
+            if let Some(lfn_entry) = entry.long_filename() {
+                lfn_run.push((index, lfn_entry));
+                continue;
+            }
+            let regular_entry = entry.regular().unwrap();
+            let name = self.check_lfn(first_cluster, path, &lfn_run, index, &regular_entry)?;
+            lfn_run.clear();
+            let attributes = regular_entry.attributes();
+            if attributes.volume_id() && !attributes.directory() {
+                continue;
+            }
+            if name == "." || name == ".." {
+                continue;
+            }
+            let child_path = if path == "/" {
+                format!("/{}", name)
+            } else {
+                format!("{}/{}", path, name)
+            };
+            self.check_entry(first_cluster, index, &regular_entry, &child_path)?;
+        }
+        let end = entries.len();
+        self.check_orphan_lfn(first_cluster, path, &mut lfn_run, end)
+    }
+
+    /// Reports LFN entries not followed by a regular entry.
+    fn check_orphan_lfn(
+        &mut self,
+        dir: Cluster,
+        path: &str,
+        lfn_run: &mut Vec<(usize, VFatLfnDirEntry)>,
+        index: usize,
+    ) -> Result<(), Error> {
+        if lfn_run.is_empty() {
+            return Ok(());
+        }
+        self.problems.push(Problem::BadLfn {
+            dir: String::from(path),
+            index,
+        });
+        if self.repair {
+            for &(i, _) in lfn_run.iter() {
+                self.vfat.dir_entry_mut(dir, i)?.mark_deleted();
+            }
+        }
+        lfn_run.clear();
+        Ok(())
+    }
+
+    /// Checks the LFN entries `lfn_run` preceding the regular entry at
+    /// `index` and returns the name of the entry.
+    fn check_lfn(
+        &mut self,
+        dir: Cluster,
+        path: &str,
+        lfn_run: &[(usize, VFatLfnDirEntry)],
+        index: usize,
+        regular_entry: &VFatRegularDirEntry,
+    ) -> Result<String, Error> {
+        if lfn_run.is_empty() {
+            return Ok(regular_entry_name(regular_entry));
+        }
+        let checksum = lfn_checksum(&regular_entry.short_name());
+        let count = lfn_run.len();
+        let valid = lfn_run.iter().enumerate().all(|(i, (_, lfn_entry))| {
+            let expected = (count - i) as u8 | if i == 0 { 0x40 } else { 0x00 };
+            lfn_entry.seq_num() == expected && lfn_entry.checksum() == checksum
+        });
+        if !valid {
+            self.problems.push(Problem::BadLfn {
+                dir: String::from(path),
+                index,
+            });
+            if self.repair {
+                for &(i, _) in lfn_run {
+                    self.vfat.dir_entry_mut(dir, i)?.mark_deleted();
+                }
+            }
+            return Ok(regular_entry_name(regular_entry));
+        }
+        let mut units = Vec::new();
+        for (_, lfn_entry) in lfn_run.iter().rev() {
+            units.extend_from_slice(&lfn_entry.name_units());
+        }
+        let len = units
+            .iter()
+            .position(|&c| c == 0x0000 || c == 0xffff)
+            .unwrap_or(units.len());
+        Ok(String::from_utf16_lossy(&units[..len]))
+    }
+
+    /// Checks the chain of the regular entry at position `index` of the
+    /// directory starting at `dir`, and recurses into it if it is a directory.
+    fn check_entry(
+        &mut self,
+        dir: Cluster,
+        index: usize,
+        regular_entry: &VFatRegularDirEntry,
+        path: &str,
+    ) -> Result<(), Error> {
+        let first = regular_entry.first_cluster();
+        let is_dir = regular_entry.attributes().directory();
+        let chain = if first.raw() == 0 {
+            ClaimedChain {
+                clusters: Vec::new(),
+                clear_first: false,
+            }
+        } else {
+            self.claim_chain(first, path)?
+        };
+        if chain.clear_first && self.repair {
+            self.vfat.update_dir_entry(dir, index, |entry| {
+                entry.set_first_cluster(Cluster::from(0));
+                entry.set_size(0);
+            })?;
+        }
+        if is_dir {
+            if !chain.clusters.is_empty() {
+                self.check_dir(first, Some(&chain.clusters), path)?;
+            }
+            return Ok(());
+        }
+
+        let cluster_size = self.vfat.cluster_size();
+        let size = regular_entry.size();
+        let needed = ((size as u64 + cluster_size - 1) / cluster_size) as usize;
+        let clusters = &chain.clusters;
+        if needed == clusters.len() || (chain.clear_first && self.repair) {
+            return Ok(());
+        }
+        self.problems.push(Problem::SizeMismatch {
+            path: String::from(path),
+            size,
+            clusters: clusters.len() as u32,
+        });
+        if !self.repair {
+            return Ok(());
+        }
+        if needed < clusters.len() {
+            for &cluster in &clusters[needed..] {
+                self.owners[cluster.raw() as usize] = None;
+            }
+            self.vfat.free_chain(clusters[needed])?;
+            if needed == 0 {
+                self.vfat.update_dir_entry(dir, index, |entry| {
+                    entry.set_first_cluster(Cluster::from(0))
+                })?;
+            } else {
+                self.vfat.set_fat_entry(clusters[needed - 1], FAT_EOC)?;
+            }
+        } else {
+            let size = (clusters.len() as u64 * cluster_size) as u32;
+            self.vfat
+                .update_dir_entry(dir, index, |entry| entry.set_size(size))?;
+        }
+        Ok(())
+    }
+
+    /// Reports (and frees) allocated clusters not owned by any entry.
+    fn check_lost_chains(&mut self) -> Result<(), Error> {
+        let end = self.owners.len();
+        let mut lost = vec![false; end];
+        let mut next = vec![None; end];
+        let mut is_target = vec![false; end];
+        for raw in 2..end {
+            if self.owners[raw].is_some() {
+                continue;
+            }
+            match self.vfat.fat_entry(Cluster::from(raw as u32))?.status() {
+                Status::Data(n) => {
+                    lost[raw] = true;
+                    if self.is_data_cluster(n) {
+                        next[raw] = Some(n.raw() as usize);
+                    }
+                }
+                Status::Eoc(_) => lost[raw] = true,
+                _ => {}
+            }
+        }
+        for raw in 2..end {
+            if let Some(n) = next[raw] {
+                if lost[n] {
+                    is_target[n] = true;
+                }
+            }
+        }
+        // Chain heads first, then whatever is left over in cycles.
+        let heads = (2..end)
+            .filter(|&raw| lost[raw] && !is_target[raw])
+            .chain(2..end)
+            .collect::<Vec<_>>();
+        for start in heads {
+            if !lost[start] {
+                continue;
+            }
+            let mut clusters = 0;
+            let mut cluster = Some(start);
+            while let Some(raw) = cluster.filter(|&raw| lost[raw]) {
+                lost[raw] = false;
+                clusters += 1;
+                if self.repair {
+                    self.vfat.set_fat_entry(Cluster::from(raw as u32), 0)?;
+                }
+                cluster = next[raw];
+            }
+            self.problems.push(Problem::LostChain {
+                start: start as u32,
+                clusters,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_free_count(&mut self) -> Result<(), Error> {
+        let mut actual = 0;
+        for raw in 2..self.owners.len() as u32 {
+            if self.vfat.fat_entry(Cluster::from(raw))?.status() == Status::Free {
+                actual += 1;
+            }
+        }
+        let recorded = self.vfat.recorded_free_clusters()?;
+        if self.repair {
+            // Written to the FSInfo sector by the final flush.
+            self.vfat.set_free_clusters(Some(actual));
+        }
+        let recorded = match recorded {
+            Some(recorded) => recorded,
+            None => return Ok(()),
+        };
+        if recorded == FSINFO_UNKNOWN || recorded == actual {
+            return Ok(());
+        }
+        self.problems.push(Problem::FreeCount { recorded, actual });
+        Ok(())
+    }
+}
+
+impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Checks the consistency of the file system and returns the problems
+    /// found. If `repair` is `true`, every problem is also repaired as
+    /// described in `Problem` and the changes are written back to the device.
+    /// Otherwise, nothing is written to the device.
+    ///
+    /// The FAT copies are checked first; the other checks only look at the
+    /// first FAT.
+    pub fn check(&mut self, repair: bool) -> Result<Vec<Problem>, Error> {
+        let end = self.data_clusters() as usize + 2;
+        let rootdir_cluster = self.rootdir_cluster();
+        let mut checker = Checker {
+            vfat: self,
+            repair,
+            owners: vec![None; end],
+            paths: Vec::new(),
+            problems: Vec::new(),
+        };
+        checker.check_fat_copies()?;
+        if checker.vfat.is_fixed_rootdir(rootdir_cluster) {
+            checker.check_dir(rootdir_cluster, None, "/")?;
+        } else {
+            let chain = checker.claim_chain(rootdir_cluster, "/")?;
+            checker.check_dir(rootdir_cluster, Some(&chain.clusters), "/")?;
+        }
+        checker.check_lost_chains()?;
+        checker.check_free_count()?;
+        let problems = checker.problems;
+        if repair {
+            self.flush()?;
+        }
+        Ok(problems)
+    }
+}
@@ -0,0 +1,108 @@
+use core::fmt;
+use shim::const_assert_size;
+
+use crate::traits::BlockDevice;
+use crate::vfat::{Error, FatType};
+
+const LEAD_SIGNATURE: u32 = 0x4161_5252;
+const STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+
+/// Value of `free_count` and `next_free` when unknown.
+pub const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// The FAT32 file system information sector.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32,
+    _reserved: [u8; 480],
+    struct_signature: u32,
+    /// Last known number of free clusters, or `FSINFO_UNKNOWN`.
+    pub free_count: u32,
+    /// Cluster from which to start looking for free clusters, or
+    /// `FSINFO_UNKNOWN`.
+    pub next_free: u32,
+    _reserved2: [u8; 12],
+    trail_signature: u32,
+}
+
+const_assert_size!(FsInfo, 512);
+
+impl FsInfo {
+    /// Returns an FSInfo structure with valid signatures.
+    pub fn new(free_count: u32, next_free: u32) -> FsInfo {
+        FsInfo {
+            lead_signature: LEAD_SIGNATURE,
+            _reserved: [0; 480],
+            struct_signature: STRUCT_SIGNATURE,
+            free_count,
+            next_free,
+            _reserved2: [0; 12],
+            trail_signature: TRAIL_SIGNATURE,
+        }
+    }
+
+    /// Reads the FSInfo structure from sector `sector` of device `device`.
+    ///
+    /// # Errors
+    ///
+    /// If any of the three FSInfo signatures is invalid, returns an error of
+    /// `BadSignature`.
+    pub fn from<T: BlockDevice>(mut device: T, sector: u64) -> Result<FsInfo, Error> {
+        let mut sector_data = vec![0; device.sector_size() as usize];
+        device.read_sector(sector, &mut sector_data)?;
+        let fsinfo = unsafe { *{ sector_data.as_ptr() as *const FsInfo } };
+        if fsinfo.lead_signature != LEAD_SIGNATURE
+            || fsinfo.struct_signature != STRUCT_SIGNATURE
+            || fsinfo.trail_signature != TRAIL_SIGNATURE
+        {
+            return Err(Error::BadSignature);
+        }
+        Ok(fsinfo)
+    }
+
+    /// Writes the FSInfo structure to sector `sector` of device `device`,
+    /// preserving the rest of the sector.
+    pub fn write<T: BlockDevice>(&self, mut device: T, sector: u64) -> Result<(), Error> {
+        let mut sector_data = vec![0; device.sector_size() as usize];
+        device.read_sector(sector, &mut sector_data)?;
+        let bytes = unsafe { core::slice::from_raw_parts(self as *const FsInfo as *const u8, 512) };
+        sector_data[..512].copy_from_slice(bytes);
+        device.write_sector(sector, &sector_data)?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_count", &{ self.free_count })
+            .field("next_free", &{ self.next_free })
+            .finish()
+    }
+}
+
+/// Usage of a file system, as returned by `VFat::statfs()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStats {
+    pub fat_type: FatType,
+    /// Size in bytes of a cluster, the allocation unit.
+    pub cluster_size: u64,
+    /// Number of data clusters.
+    pub total_clusters: u32,
+    /// Number of free data clusters.
+    pub free_clusters: u32,
+}
+
+impl FsStats {
+    /// Returns the size of the data region in bytes.
+    pub fn total_space(&self) -> u64 {
+        self.total_clusters as u64 * self.cluster_size
+    }
+
+    /// Returns the number of bytes available for new data.
+    pub fn free_space(&self) -> u64 {
+        self.free_clusters as u64 * self.cluster_size
+    }
+}
@@ -10,6 +10,11 @@ use crate::traits;
 pub struct Date(u16);
 
 impl Date {
+    /// Returns a `Date` for `year`/`month`/`day`. `year` must be in the range
+    /// [1980, 2107].
+    pub fn new(year: usize, month: u8, day: u8) -> Self {
+        Self(((year - 1980) as u16) << 9 | (month as u16) << 5 | day as u16)
+    }
     pub fn day(&self) -> u8 {
         (self.0 & 0b0000_0000__0001_1111) as u8
     }
@@ -30,6 +35,11 @@ impl Time {
     pub fn from(t: u16) -> Self {
         Self(t)
     }
+    /// Returns a `Time` for `hour`:`minute`:`second`. Seconds are stored with
+    /// a 2 second granularity.
+    pub fn new(hour: u8, minute: u8, second: u8) -> Self {
+        Self((hour as u16) << 11 | (minute as u16) << 5 | (second / 2) as u16)
+    }
     pub fn second(&self) -> u8 {
         ((self.0 & 0b0000_0000__0001_1111) * 2) as u8
     }
@@ -41,13 +51,13 @@ impl Time {
     }
 }
 
-const ATTR_READ_ONLY: u8 = 1 << 0;
-const ATTR_HIDDEN: u8 = 1 << 1;
-const ATTR_SYSTEM: u8 = 1 << 2;
-const ATTR_VOLUME_ID: u8 = 1 << 3;
-const ATTR_DIRECTORY: u8 = 1 << 4;
-const ATTR_ARCHIVE: u8 = 1 << 5;
-const ATTR_LFN: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+pub(crate) const ATTR_READ_ONLY: u8 = 1 << 0;
+pub(crate) const ATTR_HIDDEN: u8 = 1 << 1;
+pub(crate) const ATTR_SYSTEM: u8 = 1 << 2;
+pub(crate) const ATTR_VOLUME_ID: u8 = 1 << 3;
+pub(crate) const ATTR_DIRECTORY: u8 = 1 << 4;
+pub(crate) const ATTR_ARCHIVE: u8 = 1 << 5;
+pub(crate) const ATTR_LFN: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
 
 /// File attributes as represented in FAT32 on-disk structures.
 #[repr(C, packed)]
@@ -57,6 +67,9 @@ pub struct Attributes(u8);
 const ROOTDIR_ATTRIBUTES: Attributes = Attributes(ATTR_DIRECTORY);
 
 impl Attributes {
+    pub fn new(raw: u8) -> Self {
+        Self(raw)
+    }
     pub fn raw(&self) -> u8 {
         self.0
     }
@@ -108,6 +121,14 @@ impl Debug for Timestamp {
     }
 }
 
+impl Timestamp {
+    /// The earliest timestamp representable in FAT32: 1980-01-01 00:00:00.
+    pub const EPOCH: Timestamp = Timestamp {
+        date: Date(1 << 5 | 1),
+        time: Time(0),
+    };
+}
+
 const ROOTDIR_TIMESTAMP: Timestamp = Timestamp {
     date: Date(0),
     time: Time(0),
@@ -0,0 +1,273 @@
+use alloc::string::String;
+use core::mem::size_of;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::mbr::{MasterBootRecord, PartitionEntry};
+use crate::traits::BlockDevice;
+use crate::vfat::vfat::FAT_EOC;
+use crate::vfat::{BiosParameterBlock, FatType, FsInfo};
+
+/// Byte offset of the formatted partition from the start of the device.
+const PARTITION_OFFSET: u64 = 1024 * 1024;
+/// MBR partition types of the formatted partition, by FAT type: FAT12, FAT16
+/// addressed by LBA and FAT32 addressed by LBA.
+const FAT12_PARTITION_TYPE: u8 = 0x01;
+const FAT16_LBA_PARTITION_TYPE: u8 = 0x0E;
+const FAT32_LBA_PARTITION_TYPE: u8 = 0x0C;
+
+const RESERVED_SECTORS: u16 = 32;
+const FSINFO_SECTOR: u16 = 1;
+const BACKUP_BOOT_SECTOR: u16 = 6;
+const ROOTDIR_CLUSTER: u32 = 2;
+
+/// FAT12 and FAT16 file systems only reserve their boot sector, and have a
+/// fixed size root directory region of `FAT16_ROOTDIR_ENTRIES` entries.
+const FAT16_RESERVED_SECTORS: u16 = 1;
+const FAT16_ROOTDIR_ENTRIES: u16 = 512;
+
+/// Largest number of data clusters a FAT32 file system can address.
+const MAX_FAT32_CLUSTERS: u64 = 0x0FFF_FFF5 - 2;
+
+/// Options of a `format()` run.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// FAT variant of the file system. The volume must have a number of
+    /// clusters in its range.
+    pub fat_type: FatType,
+    /// Size of a cluster in bytes. Chosen from the volume size if `None`.
+    pub cluster_size: Option<u32>,
+    /// Volume label, at most 11 ASCII characters.
+    pub volume_label: String,
+    /// Number of copies of the FAT.
+    pub fats: u8,
+    /// Volume serial number.
+    pub volume_id: u32,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            fat_type: FatType::Fat32,
+            cluster_size: None,
+            volume_label: String::from("NO NAME"),
+            fats: 2,
+            volume_id: 0,
+        }
+    }
+}
+
+/// Returns the default cluster size of a `fat_type` volume of
+/// `volume_bytes` bytes. FAT12 and FAT16 volumes get the smallest cluster
+/// size that keeps their number of clusters in range.
+fn default_cluster_size(fat_type: FatType, volume_bytes: u64) -> u32 {
+    const MIB: u64 = 1024 * 1024;
+    let max_clusters = match fat_type {
+        FatType::Fat12 => 4084,
+        FatType::Fat16 => 65524,
+        FatType::Fat32 => {
+            return match volume_bytes {
+                b if b <= 260 * MIB => 512,
+                b if b <= 8 * 1024 * MIB => 4096,
+                b if b <= 16 * 1024 * MIB => 8192,
+                b if b <= 32 * 1024 * MIB => 16384,
+                _ => 32768,
+            };
+        }
+    };
+    let mut cluster_size = 512;
+    while cluster_size < 32768 && volume_bytes / cluster_size > max_clusters {
+        cluster_size *= 2;
+    }
+    cluster_size as u32
+}
+
+/// Returns the bytes of the on-disk structure `value`.
+fn struct_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+/// Writes `bytes` at the start of an otherwise zeroed sector `sector`.
+fn write_struct_sector<T: BlockDevice>(
+    device: &mut T,
+    sector: u64,
+    bytes: &[u8],
+) -> io::Result<()> {
+    let mut sector_data = vec![0; device.sector_size() as usize];
+    sector_data[..bytes.len()].copy_from_slice(bytes);
+    device.write_sector(sector, &sector_data)?;
+    Ok(())
+}
+
+/// Formats the first `total_sectors` sectors of `device` with a FAT file
+/// system of type `options.fat_type`.
+///
+/// An MBR holding a single FAT partition starting 1MiB into the device is
+/// written to sector 0. The partition receives a boot sector, zeroed FATs and
+/// an empty root directory, plus an FSInfo sector and backups of both for
+/// FAT32, so the result can be mounted with `VFat::from_mbr_part0`. Sectors
+/// outside of the MBR and the file system metadata are left untouched.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` if the options are invalid or if
+/// the device is too small or too large for the requested FAT type with the
+/// requested cluster size. I/O errors of `device` are returned as is.
+pub fn format<T: BlockDevice>(
+    mut device: T,
+    total_sectors: u64,
+    options: &FormatOptions,
+) -> io::Result<()> {
+    let bytes_per_sector = device.sector_size();
+    if bytes_per_sector < 512 || bytes_per_sector > 4096 || !bytes_per_sector.is_power_of_two() {
+        return ioerr!(InvalidInput, "unsupported sector size");
+    }
+    if options.fats == 0 || options.fats > 2 {
+        return ioerr!(InvalidInput, "number of FATs must be 1 or 2");
+    }
+    let label = &options.volume_label;
+    if label.len() > 11 || !label.bytes().all(|b| b.is_ascii_graphic() || b == b' ') {
+        return ioerr!(InvalidInput, "invalid volume label");
+    }
+    let mut volume_label = [b' '; 11];
+    for (dst, src) in volume_label.iter_mut().zip(label.bytes()) {
+        *dst = src.to_ascii_uppercase();
+    }
+
+    let fat_type = options.fat_type;
+    let start_sector = PARTITION_OFFSET / bytes_per_sector;
+    if total_sectors <= start_sector {
+        return ioerr!(InvalidInput, "volume too small for the FAT type");
+    }
+    let partition_sectors = total_sectors - start_sector;
+    if start_sector + partition_sectors > u32::max_value() as u64 {
+        return ioerr!(InvalidInput, "volume too large for an MBR partition");
+    }
+
+    let cluster_size = options.cluster_size.unwrap_or_else(|| {
+        default_cluster_size(fat_type, partition_sectors * bytes_per_sector)
+            .max(bytes_per_sector as u32)
+    }) as u64;
+    if cluster_size % bytes_per_sector != 0
+        || !(cluster_size / bytes_per_sector).is_power_of_two()
+        || cluster_size / bytes_per_sector > 128
+    {
+        return ioerr!(InvalidInput, "invalid cluster size");
+    }
+    let sectors_per_cluster = cluster_size / bytes_per_sector;
+
+    let (reserved, rootdir_entries) = match fat_type {
+        FatType::Fat32 => (RESERVED_SECTORS as u64, 0),
+        _ => (FAT16_RESERVED_SECTORS as u64, FAT16_ROOTDIR_ENTRIES),
+    };
+    let rootdir_sectors = (rootdir_entries as u64 * 32 + bytes_per_sector - 1) / bytes_per_sector;
+    let entry_bits = fat_type.entry_bits() as u64;
+
+    // The FAT size depends on the number of clusters, which depends on the
+    // FAT size: grow the FAT until it covers every cluster it leaves room for.
+    let fats = options.fats as u64;
+    let mut sectors_per_fat = 1;
+    let clusters = loop {
+        let metadata = reserved + fats * sectors_per_fat + rootdir_sectors;
+        let data_sectors = partition_sectors.saturating_sub(metadata);
+        let clusters = data_sectors / sectors_per_cluster;
+        let fat_bytes = ((clusters + 2) * entry_bits + 7) / 8;
+        let needed = (fat_bytes + bytes_per_sector - 1) / bytes_per_sector;
+        if needed <= sectors_per_fat {
+            break clusters;
+        }
+        sectors_per_fat = needed;
+    };
+    if clusters > MAX_FAT32_CLUSTERS {
+        return ioerr!(InvalidInput, "too many clusters for FAT32");
+    }
+    if FatType::from_clusters(clusters as u32) != fat_type {
+        return ioerr!(InvalidInput, "volume size out of range for the FAT type");
+    }
+
+    let partition_type = match fat_type {
+        FatType::Fat12 => FAT12_PARTITION_TYPE,
+        FatType::Fat16 => FAT16_LBA_PARTITION_TYPE,
+        FatType::Fat32 => FAT32_LBA_PARTITION_TYPE,
+    };
+    let mut partition_table = [PartitionEntry::new(0, 0, 0); 4];
+    partition_table[0] = PartitionEntry::new(
+        partition_type,
+        start_sector as u32,
+        partition_sectors as u32,
+    );
+    let mbr = MasterBootRecord::new(partition_table);
+    write_struct_sector(&mut device, 0, struct_bytes(&mbr))?;
+
+    let mut ebpb = BiosParameterBlock::new(fat_type, options.volume_id, volume_label);
+    ebpb.bytes_per_sector = bytes_per_sector as u16;
+    ebpb.sectors_per_cluster = sectors_per_cluster as u8;
+    ebpb.reserved_sectors = reserved as u16;
+    ebpb.fats = options.fats;
+    ebpb.sectors_per_track = 63;
+    ebpb.heads = 255;
+    ebpb.hidden_sectors = start_sector as u32;
+
+    let zeroes = vec![0; bytes_per_sector as usize];
+    for sector in 0..reserved {
+        device.write_sector(start_sector + sector, &zeroes)?;
+    }
+    let mut first_fat_sector = zeroes.clone();
+    if fat_type == FatType::Fat32 {
+        ebpb.logical_sectors_32 = partition_sectors as u32;
+        ebpb.sectors_per_fat_32 = sectors_per_fat as u32;
+        ebpb.rootdir_cluster = ROOTDIR_CLUSTER;
+        ebpb.fsinfo_sector = FSINFO_SECTOR;
+        ebpb.boot_sector_backup_sector = BACKUP_BOOT_SECTOR;
+        // The root directory occupies the first cluster.
+        let fsinfo = FsInfo::new(clusters as u32 - 1, ROOTDIR_CLUSTER + 1);
+        for &boot_sector in &[0, BACKUP_BOOT_SECTOR as u64] {
+            let sector = start_sector + boot_sector;
+            write_struct_sector(&mut device, sector, struct_bytes(&ebpb))?;
+            write_struct_sector(
+                &mut device,
+                sector + FSINFO_SECTOR as u64,
+                struct_bytes(&fsinfo),
+            )?;
+        }
+        let media_entry = 0x0FFF_FF00 | ebpb.fat_id as u32;
+        for (i, &entry) in [media_entry, FAT_EOC, FAT_EOC].iter().enumerate() {
+            first_fat_sector[i * 4..i * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+    } else {
+        ebpb.max_num_dir = rootdir_entries;
+        if partition_sectors <= u16::max_value() as u64 {
+            ebpb.logical_sectors_16 = partition_sectors as u16;
+        } else {
+            ebpb.logical_sectors_32 = partition_sectors as u32;
+        }
+        ebpb.sectors_per_fat_16 = sectors_per_fat as u16;
+        write_struct_sector(&mut device, start_sector, &ebpb.to_fat16_layout())?;
+        // The media entry and an end of chain entry, packed for FAT12.
+        first_fat_sector[0] = ebpb.fat_id;
+        let reserved_entries = (2 * entry_bits / 8) as usize;
+        for byte in &mut first_fat_sector[1..reserved_entries] {
+            *byte = 0xFF;
+        }
+    }
+
+    let fat_start = start_sector + reserved;
+    for fat in 0..fats {
+        let start = fat_start + fat * sectors_per_fat;
+        device.write_sector(start, &first_fat_sector)?;
+        for sector in 1..sectors_per_fat {
+            device.write_sector(start + sector, &zeroes)?;
+        }
+    }
+
+    let rootdir_start = fat_start + fats * sectors_per_fat;
+    let rootdir_len = match fat_type {
+        FatType::Fat32 => sectors_per_cluster,
+        _ => rootdir_sectors,
+    };
+    for sector in 0..rootdir_len {
+        device.write_sector(rootdir_start + sector, &zeroes)?;
+    }
+    Ok(())
+}
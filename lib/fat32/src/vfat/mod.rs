@@ -6,15 +6,23 @@ pub(crate) mod entry;
 pub(crate) mod error;
 pub(crate) mod fat;
 pub(crate) mod file;
+pub(crate) mod fsck;
+pub(crate) mod fsinfo;
 pub(crate) mod metadata;
+pub(crate) mod mkfs;
 pub(crate) mod vfat;
 
+pub use self::cache::{CacheStats, DEFAULT_CACHE_CAPACITY, DEFAULT_READAHEAD};
 pub use self::dir::Dir;
 pub use self::ebpb::BiosParameterBlock;
 pub use self::entry::Entry;
 pub use self::error::Error;
+pub use self::fat::FatType;
 pub use self::file::File;
+pub use self::fsck::Problem;
+pub use self::fsinfo::{FsInfo, FsStats, FSINFO_UNKNOWN};
 pub use self::metadata::{Attributes, Date, Metadata, Time, Timestamp};
+pub use self::mkfs::{format, FormatOptions};
 pub use self::vfat::{Chain, VFat, VFatHandle};
 
 pub(crate) use self::cache::{BlockDeviceCached, BlockDevicePartition, Partition};
@@ -1,3 +1,4 @@
+use core::cmp;
 use core::fmt::Debug;
 use core::marker::PhantomData;
 use core::mem::size_of;
@@ -13,14 +14,20 @@ use shim::path::Path;
 
 // use crate::util::print_hex;
 
+use crate::gpt::{Guid, GuidPartitionTable};
 use crate::mbr::MasterBootRecord;
 use crate::traits::{BlockDevice, FileSystem};
 use crate::util::SliceExt;
-use crate::vfat::cache::read_n_sectors;
+use crate::vfat::dir::{VFatDirEntry, VFatRegularDirEntry};
 use crate::vfat::entry::EntryValue;
 use crate::vfat::metadata::ROOTDIR_METADATA;
 use crate::vfat::{BiosParameterBlock, BlockDeviceCached, BlockDevicePartition, Partition};
-use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status};
+use crate::vfat::{CacheStats, Cluster, Dir, Entry, Error, FatEntry, FatType, File, FsInfo};
+use crate::vfat::{FsStats, Status, Timestamp, FSINFO_UNKNOWN};
+
+/// FAT entry value used to mark the last cluster of a chain. Truncated to the
+/// entry width for FAT12 and FAT16.
+pub(crate) const FAT_EOC: u32 = 0x0fff_ffff;
 
 #[derive(Debug)]
 pub struct Chain<HANDLE: VFatHandle> {
@@ -68,33 +75,135 @@ pub struct VFat<HANDLE: VFatHandle> {
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    fats: u8,
+    fat_type: FatType,
     fat_start_sector: u64,
+    /// First sector of the fixed size root directory region (FAT12/16 only).
+    rootdir_start_sector: u64,
+    /// Number of entries of the fixed size root directory region (FAT12/16
+    /// only).
+    rootdir_entries: usize,
     data_start_sector: u64,
+    /// First cluster of the root directory, 0 for the fixed size root
+    /// directory region of FAT12/16.
     rootdir_cluster: Cluster,
+    /// Sector of the FSInfo structure (FAT32 only).
+    fsinfo_sector: Option<u64>,
+    data_clusters: u32,
+    /// Cluster from which to look for a free cluster.
+    next_free: Cluster,
+    /// Number of free clusters, `None` until known.
+    free_clusters: Option<u32>,
+    /// Whether `next_free` or `free_clusters` changed since the FSInfo
+    /// sector was last written.
+    fsinfo_dirty: bool,
+    clock: fn() -> Timestamp,
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
-    pub fn from_mbr_part0<T>(mut device: T) -> Result<HANDLE, Error>
+    pub fn from_mbr_part0<T>(device: T) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        VFat::from_mbr_part(device, 0)
+    }
+
+    /// Mounts the FAT partition `index` (0 to 3) of the MBR of `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such partition or its type is not a
+    /// FAT partition type.
+    pub fn from_mbr_part<T>(mut device: T, index: usize) -> Result<HANDLE, Error>
     where
         T: BlockDevice + 'static,
     {
-        let phy_sector_size = device.sector_size();
         let mbr = MasterBootRecord::from(&mut device)?;
-        let part_data = mbr.partition_table[0];
-        if part_data.partition_type != 0xB && part_data.partition_type != 0xC {
-            // Paritition type is not FAT32
-            return Err(Error::NotFound);
-        }
-        let start_phy_sector = part_data.relative_sector;
-        let phy_sectors = part_data.total_sectors;
-        let ebpb = BiosParameterBlock::from(&mut device, start_phy_sector as u64)?;
+        let part_data = match mbr.partition_table.get(index) {
+            Some(part_data) => *part_data,
+            None => return Err(Error::NotFound),
+        };
+        match part_data.partition_type {
+            // FAT12, FAT16 (< 32 MiB), FAT16B, FAT32 (CHS), FAT32 (LBA), FAT16B (LBA)
+            0x01 | 0x04 | 0x06 | 0x0B | 0x0C | 0x0E => {}
+            _ => return Err(Error::NotFound),
+        }
+        VFat::from_partition(
+            device,
+            part_data.relative_sector as u64,
+            part_data.total_sectors as u64,
+        )
+    }
+
+    /// Mounts the partition at index `index` of the GPT partition entry
+    /// array of `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such partition.
+    pub fn from_gpt_part<T>(mut device: T, index: usize) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        match gpt.entries.get(index).filter(|e| e.is_used()) {
+            Some(part) => VFat::from_partition(device, part.first_lba, part.num_sectors()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    /// Mounts the first GPT partition of `device` with partition type
+    /// `type_guid`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such partition.
+    pub fn from_gpt_type<T>(mut device: T, type_guid: &Guid) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        match gpt.find_by_type(type_guid) {
+            Some(part) => VFat::from_partition(device, part.first_lba, part.num_sectors()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    /// Mounts the first GPT partition of `device` named `label`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such partition.
+    pub fn from_gpt_label<T>(mut device: T, label: &str) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        match gpt.find_by_label(label) {
+            Some(part) => VFat::from_partition(device, part.first_lba, part.num_sectors()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    /// Mounts the FAT file system in the `phy_sectors` physical sectors of
+    /// `device` starting at sector `start_phy_sector`.
+    pub fn from_partition<T>(
+        mut device: T,
+        start_phy_sector: u64,
+        phy_sectors: u64,
+    ) -> Result<HANDLE, Error>
+    where
+        T: BlockDevice + 'static,
+    {
+        let phy_sector_size = device.sector_size();
+        let ebpb = BiosParameterBlock::from(&mut device, start_phy_sector)?;
         let logical_sector_size = ebpb.bytes_per_sector;
         if (logical_sector_size as u64) < phy_sector_size {
             return Err(Error::Fat("logical sector size < physical sector size"));
         }
         let logical_sectors = ebpb.logical_sectors();
-        let factor = logical_sector_size as u32 / phy_sector_size as u32;
-        if logical_sectors > phy_sectors.saturating_mul(factor) {
+        let factor = logical_sector_size as u64 / phy_sector_size;
+        if logical_sectors as u64 > phy_sectors.saturating_mul(factor) {
             return Err(Error::Fat(
                 "logical sectors exceeds physical sectors * factor",
             ));
@@ -102,31 +211,77 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         let part = BlockDevicePartition::new(
             device,
             Partition {
-                start: start_phy_sector as u64,
+                start: start_phy_sector,
                 num_sectors: logical_sectors as u64,
                 sector_size: logical_sector_size as u64,
             },
         );
         let part_cached = BlockDeviceCached::new(part);
-        let vfat = VFat {
+        if ebpb.sectors_per_cluster == 0 {
+            return Err(Error::Fat("zero sectors per cluster"));
+        }
+        let rootdir_start_sector =
+            ebpb.reserved_sectors as u64 + ebpb.fats as u64 * ebpb.sectors_per_fat() as u64;
+        let data_start_sector = rootdir_start_sector + ebpb.rootdir_sectors() as u64;
+        let clusters = (logical_sectors as u64).saturating_sub(data_start_sector)
+            / ebpb.sectors_per_cluster as u64;
+        let fat_type = FatType::from_clusters(clusters as u32);
+        if (fat_type == FatType::Fat32) != (ebpb.sectors_per_fat_16 == 0) {
+            return Err(Error::Fat("FAT type does not match the cluster count"));
+        }
+        let fat_entries = ebpb.sectors_per_fat() as u64 * logical_sector_size as u64 * 8
+            / fat_type.entry_bits() as u64;
+        let data_clusters = cmp::min(clusters, fat_entries.saturating_sub(2));
+        let fsinfo_sector = match { ebpb.fsinfo_sector } {
+            0 | 0xFFFF => None,
+            _ if fat_type != FatType::Fat32 => None,
+            sector if sector >= ebpb.reserved_sectors => None,
+            sector => Some(sector as u64),
+        };
+        let mut vfat = VFat {
             phantom: PhantomData::<HANDLE>,
             device: part_cached,
             bytes_per_sector: logical_sector_size,
             sectors_per_cluster: ebpb.sectors_per_cluster,
             sectors_per_fat: ebpb.sectors_per_fat(),
+            fats: ebpb.fats,
+            fat_type,
             fat_start_sector: ebpb.reserved_sectors as u64,
-            data_start_sector: ebpb.reserved_sectors as u64
-                + ebpb.fats as u64 * ebpb.sectors_per_fat() as u64,
+            rootdir_start_sector,
+            rootdir_entries: ebpb.max_num_dir as usize,
+            data_start_sector,
             rootdir_cluster: Cluster::from(ebpb.rootdir_cluster),
+            fsinfo_sector,
+            data_clusters: data_clusters as u32,
+            next_free: Cluster::from(2),
+            free_clusters: None,
+            fsinfo_dirty: false,
+            clock: || Timestamp::EPOCH,
         };
+        vfat.load_fsinfo()?;
         Ok(HANDLE::new(vfat))
     }
 
+    /// Mounts the first FAT partition of `device`: the first basic data or
+    /// EFI system partition if `device` has a GPT, or MBR partition 0
+    /// otherwise.
     pub fn from<T>(mut device: T) -> Result<HANDLE, Error>
     where
         T: BlockDevice + 'static,
     {
-        VFat::from_mbr_part0(device)
+        let mbr = MasterBootRecord::from(&mut device)?;
+        if !GuidPartitionTable::is_protective(&mbr) {
+            return VFat::from_mbr_part0(device);
+        }
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        let part = gpt
+            .partitions()
+            .map(|(_, part)| part)
+            .find(|part| part.type_guid == Guid::BASIC_DATA || part.type_guid == Guid::EFI_SYSTEM);
+        match part {
+            Some(part) => VFat::from_partition(device, part.first_lba, part.num_sectors()),
+            None => Err(Error::NotFound),
+        }
     }
 
     pub fn cluster_sector(&self, cluster: Cluster) -> u64 {
@@ -140,16 +295,47 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
     // Read from an offset of a cluster into a buffer.
     pub fn read_cluster(&mut self, cluster: Cluster, buf: &mut [u8]) -> io::Result<usize> {
         let sector = self.cluster_sector(cluster);
-        read_n_sectors(
-            &mut self.device,
-            sector,
-            self.sectors_per_cluster as usize,
-            buf,
-        )
+        self.device
+            .read_sectors(sector, self.sectors_per_cluster as usize, buf)
     }
 
-    // Read all of the clusters chained from a starting cluster into a vector.
+    /// Reads the clusters of the chain starting at `cluster` that follow each
+    /// other on disk, at most `max` of them, into `buf` with a single device
+    /// read. Returns the number of bytes read.
+    pub fn read_cluster_run(
+        &mut self,
+        cluster: Cluster,
+        max: usize,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        let mut count = 1;
+        while count < max {
+            let last = Cluster::from(cluster.raw() + count as u32 - 1);
+            match self.fat_entry(last)?.status() {
+                Status::Data(next) if next.raw() == last.raw() + 1 => count += 1,
+                _ => break,
+            }
+        }
+        let sectors = count * self.sectors_per_cluster as usize;
+        let len = cmp::min(buf.len(), count * self.cluster_size() as usize);
+        self.device
+            .read_sectors(self.cluster_sector(cluster), sectors, &mut buf[..len])
+    }
+
+    // Read all of the clusters chained from a starting cluster into a vector. A start cluster of 0
+    // reads the fixed size root directory region of FAT12/16.
     pub fn read_chain(&mut self, start: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.is_fixed_rootdir(start) {
+            let sector_size = self.device.sector_size() as usize;
+            let len = self.rootdir_entries * size_of::<VFatDirEntry>();
+            let sectors = (len + sector_size - 1) / sector_size;
+            let start = buf.len();
+            buf.resize(start + sectors * sector_size, 0);
+            self.device
+                .read_sectors(self.rootdir_start_sector, sectors, &mut buf[start..])?;
+            buf.truncate(start + len);
+            return Ok(len);
+        }
         let mut cluster_data = vec![0; self.cluster_size() as usize];
         let mut next = start;
         let mut read_bytes = 0;
@@ -170,18 +356,383 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         Ok(read_bytes)
     }
 
-    // Return a reference to a `FatEntry` for a cluster where the reference points directly into a
-    // cached sector.
-    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
-        use core::mem::size_of;
-        let fat_entries_per_sector = self.device.sector_size() as usize / size_of::<FatEntry>();
-        let sector = self.fat_start_sector + cluster.raw() as u64 / (fat_entries_per_sector as u64);
-        let offset = cluster.raw() as usize % (fat_entries_per_sector as usize);
-        let offset_bytes = offset * size_of::<FatEntry>();
-        let sector_data = self.device.get(sector)?;
+    // Write `buf` into a cluster starting at byte `offset` of the cluster. At most the remaining
+    // bytes of the cluster are written; the number of bytes written is returned.
+    pub fn write_cluster(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8],
+    ) -> io::Result<usize> {
+        let sector_size = self.device.sector_size() as usize;
+        let len = cmp::min(
+            buf.len(),
+            (self.cluster_size() as usize).saturating_sub(offset),
+        );
+        let first_sector = self.cluster_sector(cluster);
+        let mut written = 0;
+        while written < len {
+            let pos = offset + written;
+            let sector_offset = pos % sector_size;
+            let n = cmp::min(len - written, sector_size - sector_offset);
+            let sector_data = self
+                .device
+                .get_mut(first_sector + (pos / sector_size) as u64)?;
+            sector_data[sector_offset..sector_offset + n]
+                .copy_from_slice(&buf[written..written + n]);
+            written += n;
+        }
+        Ok(written)
+    }
+
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    pub fn fats(&self) -> u8 {
+        self.fats
+    }
+
+    /// Returns the number of data clusters. Valid cluster numbers range from 2
+    /// to `data_clusters() + 1`.
+    pub fn data_clusters(&self) -> u32 {
+        self.data_clusters
+    }
+
+    /// Reads the FSInfo structure, if the file system has one.
+    pub fn fsinfo(&mut self) -> Result<Option<FsInfo>, Error> {
+        match self.fsinfo_sector {
+            Some(sector) => Ok(Some(FsInfo::from(&mut self.device, sector)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Takes the free cluster count and next free cluster hint from the
+    /// FSInfo sector when they are in range. An FSInfo sector with invalid
+    /// signatures is ignored and never written.
+    fn load_fsinfo(&mut self) -> Result<(), Error> {
+        let fsinfo = match self.fsinfo() {
+            Ok(Some(fsinfo)) => fsinfo,
+            Ok(None) => return Ok(()),
+            Err(Error::BadSignature) => {
+                self.fsinfo_sector = None;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        if fsinfo.free_count <= self.data_clusters {
+            self.free_clusters = Some(fsinfo.free_count);
+        }
+        if fsinfo.next_free >= 2 && fsinfo.next_free < self.data_clusters + 2 {
+            self.next_free = Cluster::from(fsinfo.next_free);
+        }
+        Ok(())
+    }
+
+    /// Writes the free cluster count and next free cluster hint to the FSInfo
+    /// sector, if the file system has one.
+    fn store_fsinfo(&mut self) -> io::Result<()> {
+        let mut fsinfo = match self.fsinfo() {
+            Ok(Some(fsinfo)) => fsinfo,
+            Ok(None) => return Ok(()),
+            Err(Error::Io(e)) => return Err(e),
+            Err(_) => return ioerr!(InvalidData, "invalid FSInfo sector"),
+        };
+        fsinfo.free_count = self.free_clusters.unwrap_or(FSINFO_UNKNOWN);
+        fsinfo.next_free = self.next_free.raw();
+        match self.set_fsinfo(&fsinfo) {
+            Ok(()) => Ok(()),
+            Err(Error::Io(e)) => Err(e),
+            Err(_) => ioerr!(InvalidData, "invalid FSInfo sector"),
+        }
+    }
+
+    /// Returns the free cluster count the FSInfo sector holds once flushed, or
+    /// `None` if the file system has no FSInfo sector.
+    pub(crate) fn recorded_free_clusters(&mut self) -> Result<Option<u32>, Error> {
+        let fsinfo = match self.fsinfo()? {
+            Some(fsinfo) => fsinfo,
+            None => return Ok(None),
+        };
+        if self.fsinfo_dirty {
+            Ok(Some(self.free_clusters.unwrap_or(FSINFO_UNKNOWN)))
+        } else {
+            Ok(Some(fsinfo.free_count))
+        }
+    }
+
+    /// Returns the number of free clusters. The FAT is scanned the first time
+    /// if the count was not known from the FSInfo sector.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        if let Some(free) = self.free_clusters {
+            return Ok(free);
+        }
+        let mut free = 0;
+        for raw in 2..self.data_clusters + 2 {
+            if self.fat_entry(Cluster::from(raw))?.status() == Status::Free {
+                free += 1;
+            }
+        }
+        // The scanned count is only cached: the FSInfo sector is not written
+        // unless the FAT itself changes.
+        self.free_clusters = Some(free);
+        Ok(free)
+    }
+
+    /// Overrides the number of free clusters, `None` meaning unknown. It is
+    /// written to the FSInfo sector on the next `flush()`.
+    pub(crate) fn set_free_clusters(&mut self, free: Option<u32>) {
+        self.free_clusters = free;
+        self.fsinfo_dirty = true;
+    }
+
+    /// Returns the number of bytes available for new data.
+    pub fn free_space(&mut self) -> io::Result<u64> {
+        Ok(self.free_clusters()? as u64 * self.cluster_size())
+    }
+
+    /// Returns the size of the data region in bytes.
+    pub fn total_space(&self) -> u64 {
+        self.data_clusters as u64 * self.cluster_size()
+    }
+
+    /// Returns the usage of the file system.
+    pub fn statfs(&mut self) -> io::Result<FsStats> {
+        Ok(FsStats {
+            fat_type: self.fat_type,
+            cluster_size: self.cluster_size(),
+            total_clusters: self.data_clusters,
+            free_clusters: self.free_clusters()?,
+        })
+    }
+
+    /// Writes `fsinfo` back to the FSInfo sector, if the file system has one.
+    pub fn set_fsinfo(&mut self, fsinfo: &FsInfo) -> Result<(), Error> {
+        match self.fsinfo_sector {
+            Some(sector) => fsinfo.write(&mut self.device, sector),
+            None => Ok(()),
+        }
+    }
+
+    // Return whether `cluster` refers to the fixed size root directory region of FAT12/16.
+    pub(crate) fn is_fixed_rootdir(&self, cluster: Cluster) -> bool {
+        self.fat_type != FatType::Fat32 && cluster.raw() == 0
+    }
+
+    // Return the (sector, byte offset) of byte `index` of the FAT copy `fat`.
+    fn fat_byte_location(&self, fat: u8, index: u64) -> (u64, usize) {
+        let sector_size = self.device.sector_size();
+        let sector =
+            self.fat_start_sector + fat as u64 * self.sectors_per_fat as u64 + index / sector_size;
+        (sector, (index % sector_size) as usize)
+    }
+
+    // Return the index of the first byte of the FAT entry for `cluster` along with the number of
+    // bytes to access for it. FAT12 entries span 2 bytes that may lie in different sectors.
+    fn fat_entry_bytes(&self, cluster: Cluster) -> (u64, usize) {
+        let cluster = cluster.raw() as u64;
+        match self.fat_type {
+            FatType::Fat12 => (cluster + cluster / 2, 2),
+            FatType::Fat16 => (cluster * 2, 2),
+            FatType::Fat32 => (cluster * 4, 4),
+        }
+    }
+
+    // Read the raw little endian bytes holding the FAT entry for `cluster` in FAT copy `fat`.
+    fn read_raw_fat_entry(&mut self, fat: u8, cluster: Cluster) -> io::Result<u32> {
+        let (index, len) = self.fat_entry_bytes(cluster);
         let mut bytes = [0; 4];
-        bytes.copy_from_slice(&sector_data[offset_bytes..offset_bytes + 4]);
-        Ok(FatEntry(u32::from_le_bytes(bytes)))
+        for i in 0..len {
+            let (sector, offset) = self.fat_byte_location(fat, index + i as u64);
+            bytes[i] = self.device.get(sector)?[offset];
+        }
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    // Write back the raw bytes of the FAT entry for `cluster` in FAT copy `fat`.
+    fn write_raw_fat_entry(&mut self, fat: u8, cluster: Cluster, raw: u32) -> io::Result<()> {
+        let (index, len) = self.fat_entry_bytes(cluster);
+        let bytes = raw.to_le_bytes();
+        for i in 0..len {
+            let (sector, offset) = self.fat_byte_location(fat, index + i as u64);
+            self.device.get_mut(sector)?[offset] = bytes[i];
+        }
+        Ok(())
+    }
+
+    // Return the `FatEntry` for a cluster.
+    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        self.fat_entry_in(0, cluster)
+    }
+
+    // Return the `FatEntry` for a cluster in FAT copy `fat`.
+    pub(crate) fn fat_entry_in(&mut self, fat: u8, cluster: Cluster) -> io::Result<FatEntry> {
+        let mut raw = self.read_raw_fat_entry(fat, cluster)?;
+        if self.fat_type == FatType::Fat12 && cluster.raw() % 2 == 1 {
+            raw >>= 4;
+        }
+        Ok(FatEntry(raw & self.fat_type.entry_mask(), self.fat_type))
+    }
+
+    // Set the FAT entry for `cluster` to `value` in every copy of the FAT. The reserved upper 4
+    // bits of FAT32 entries and the bits of neighbouring FAT12 entries are preserved.
+    pub fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let mut mask = self.fat_type.entry_mask();
+        let mut value = value & mask;
+        if self.fat_type == FatType::Fat12 && cluster.raw() % 2 == 1 {
+            mask <<= 4;
+            value <<= 4;
+        }
+        for fat in 0..self.fats {
+            let raw = self.read_raw_fat_entry(fat, cluster)?;
+            self.write_raw_fat_entry(fat, cluster, (raw & !mask) | value)?;
+        }
+        Ok(())
+    }
+
+    // Find a free cluster and mark it as the end of a chain. If `prev` is `Some`, the new cluster
+    // is linked after `prev`.
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let end = self.data_clusters + 2;
+        let start = cmp::max(self.next_free.raw(), 2);
+        let mut found = None;
+        for raw in (start..end).chain(2..start) {
+            let cluster = Cluster::from(raw);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                found = Some(cluster);
+                break;
+            }
+        }
+        let cluster = match found {
+            Some(cluster) => cluster,
+            None => {
+                self.set_free_clusters(Some(0));
+                return ioerr!(Other, "no free clusters");
+            }
+        };
+        self.set_fat_entry(cluster, FAT_EOC)?;
+        if let Some(prev) = prev {
+            self.set_fat_entry(prev, cluster.raw())?;
+        }
+        self.next_free = Cluster::from(cluster.raw() + 1);
+        let free = self.free_clusters.map(|free| free.saturating_sub(1));
+        self.set_free_clusters(free);
+        Ok(cluster)
+    }
+
+    // Set every FAT entry of the chain starting at `start` to free.
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        let mut next = Some(start);
+        while let Some(cluster) = next {
+            next = match self.fat_entry(cluster)?.status() {
+                Status::Data(next) => Some(next),
+                Status::Eoc(_) => None,
+                _ => return ioerr!(InvalidData, "Invalid chain fat entry"),
+            };
+            self.set_fat_entry(cluster, 0)?;
+            if cluster < self.next_free {
+                self.next_free = cluster;
+            }
+            let free = self.free_clusters.map(|free| free + 1);
+            self.set_free_clusters(free);
+        }
+        Ok(())
+    }
+
+    // Fill a cluster with zeroes.
+    pub fn zero_cluster(&mut self, cluster: Cluster) -> io::Result<()> {
+        let zeroes = vec![0; self.cluster_size() as usize];
+        self.write_cluster(cluster, 0, &zeroes)?;
+        Ok(())
+    }
+
+    pub fn rootdir_cluster(&self) -> Cluster {
+        self.rootdir_cluster
+    }
+
+    // Apply `f` to the regular directory entry at position `index` of the directory that starts
+    // at cluster `dir`. The modified entry is written back into the cached sector.
+    pub(crate) fn update_dir_entry<F>(&mut self, dir: Cluster, index: usize, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut VFatRegularDirEntry),
+    {
+        f(self.dir_entry_mut(dir, index)?.regular_mut());
+        Ok(())
+    }
+
+    // Return a reference to the raw directory entry at position `index` of the directory that
+    // starts at cluster `dir`. The reference points directly into a cached sector, which is marked
+    // dirty.
+    pub(crate) fn dir_entry_mut(
+        &mut self,
+        dir: Cluster,
+        index: usize,
+    ) -> io::Result<&mut VFatDirEntry> {
+        let entry_size = size_of::<VFatDirEntry>();
+        let sector_size = self.device.sector_size() as usize;
+        if self.is_fixed_rootdir(dir) {
+            if index >= self.rootdir_entries {
+                return ioerr!(InvalidInput, "index past the end of the root directory");
+            }
+            let offset = index * entry_size;
+            let sector = self.rootdir_start_sector + (offset / sector_size) as u64;
+            let sector_data = self.device.get_mut(sector)?;
+            let offset = offset % sector_size;
+            return Ok(unsafe {
+                &mut *(sector_data[offset..offset + entry_size].as_mut_ptr() as *mut VFatDirEntry)
+            });
+        }
+        let entries_per_cluster = self.cluster_size() as usize / entry_size;
+        let mut cluster = dir;
+        for _ in 0..index / entries_per_cluster {
+            cluster = match self.fat_entry(cluster)?.status() {
+                Status::Data(next) => next,
+                _ => return ioerr!(InvalidData, "Invalid chain fat entry"),
+            };
+        }
+        let offset = (index % entries_per_cluster) * entry_size;
+        let sector = self.cluster_sector(cluster) + (offset / sector_size) as u64;
+        let offset = offset % sector_size;
+        let sector_data = self.device.get_mut(sector)?;
+        Ok(unsafe {
+            &mut *(sector_data[offset..offset + entry_size].as_mut_ptr() as *mut VFatDirEntry)
+        })
+    }
+
+    /// Writes the FSInfo sector if needed, then all modified sectors back to
+    /// the underlying device.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.fsinfo_dirty {
+            self.store_fsinfo()?;
+            self.fsinfo_dirty = false;
+        }
+        self.device.flush()
+    }
+
+    /// Returns the access statistics of the sector cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.stats()
+    }
+
+    /// Changes the maximum number of sectors kept in the sector cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn set_cache_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        self.device.set_capacity(capacity)
+    }
+
+    /// Sets the function used to timestamp modified entries. Defaults to one
+    /// always returning `Timestamp::EPOCH`.
+    pub fn set_clock(&mut self, clock: fn() -> Timestamp) {
+        self.clock = clock;
+    }
+
+    /// Returns the current time as reported by the clock set with
+    /// `set_clock()`.
+    pub fn now(&self) -> Timestamp {
+        (self.clock)()
     }
 }
 
@@ -223,4 +774,72 @@ impl<HANDLE: VFatHandle> FileSystem for HANDLE {
             _name: String::from(ROOTDIR_NAME),
         })
     }
+
+    fn create_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = open_parent(self, path.as_ref())?;
+        parent.create_file(name)
+    }
+
+    fn create_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::Dir> {
+        let (parent, name) = open_parent(self, path.as_ref())?;
+        parent.create_dir(name)
+    }
+
+    fn remove<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (parent, name) = open_parent(self, path.as_ref())?;
+        parent.remove(name)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+        use crate::traits::Entry;
+
+        let (parent, name) = open_parent(self, from.as_ref())?;
+        let (dest, new_name) = open_parent(self, to.as_ref())?;
+        if parent.find(name)?.is_dir() {
+            // Refuse to move a directory into itself or one of its descendants.
+            let moved = parent.find(name)?.into_dir().unwrap().first_cluster;
+            let root = self.lock(|vfat| vfat.rootdir_cluster);
+            let mut ancestor = dest.clone();
+            while ancestor.first_cluster != root {
+                if ancestor.first_cluster == moved {
+                    return ioerr!(InvalidInput, "cannot move a directory into itself");
+                }
+                ancestor = match ancestor.find("..")?.into_dir() {
+                    Some(dir) => dir,
+                    None => return ioerr!(InvalidData, "'..' is not a directory"),
+                };
+            }
+        }
+        parent.rename(name, &dest, new_name)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.lock(|vfat| vfat.cache_stats()))
+    }
+
+    fn statfs(&self) -> io::Result<FsStats> {
+        self.lock(|vfat| vfat.statfs())
+    }
+}
+
+/// Opens the parent directory of `path` and returns it along with the last
+/// component of `path`.
+fn open_parent<'a, HANDLE: VFatHandle>(
+    vfat: &HANDLE,
+    path: &'a Path,
+) -> io::Result<(Dir<HANDLE>, &'a str)> {
+    use crate::traits::Entry;
+
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return ioerr!(InvalidInput, "path has no file name"),
+    };
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return ioerr!(InvalidInput, "path has no parent"),
+    };
+    match vfat.open(parent)?.into_dir() {
+        Some(dir) => Ok((dir, name)),
+        None => ioerr!(NotFound, "directory not found"),
+    }
 }
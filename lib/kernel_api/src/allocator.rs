@@ -0,0 +1,127 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+
+use crate::syscall::sbrk;
+
+/// Size of the smallest block: bin `k` holds blocks of `2^(BINS_START_K + k)`
+/// bytes.
+const BINS_START_K: usize = 3;
+const BINS_LEN: usize = 30;
+
+/// Minimum number of bytes the heap grows by at once, a page.
+const HEAP_GROWTH: usize = 64 * 1024;
+
+/// Returns the index of the bin holding blocks large and aligned enough for
+/// `layout`, and the size of its blocks.
+fn bin_of(layout: Layout) -> (usize, usize) {
+    let size = core::cmp::max(layout.size(), layout.align());
+    let size = core::cmp::max(size.next_power_of_two(), 1 << BINS_START_K);
+    (size.trailing_zeros() as usize - BINS_START_K, size)
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// The state of the heap: lists of free blocks, one per size class, and the
+/// part of the heap that has never been handed out.
+struct Heap {
+    bins: [*mut usize; BINS_LEN],
+    start: usize,
+    end: usize,
+}
+
+impl Heap {
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (index, size) = bin_of(layout);
+        if index >= BINS_LEN {
+            return ptr::null_mut();
+        }
+        let block = self.bins[index];
+        if !block.is_null() {
+            self.bins[index] = *block as *mut usize;
+            return block as *mut u8;
+        }
+
+        // Blocks are aligned to their size, which is at least the alignment.
+        if self.end == 0 {
+            match sbrk(0) {
+                Ok(brk) => {
+                    self.start = brk as usize;
+                    self.end = brk as usize;
+                }
+                Err(_) => return ptr::null_mut(),
+            }
+        }
+        let start = align_up(self.start, size);
+        if start + size > self.end {
+            let incr = align_up(start + size - self.end, HEAP_GROWTH);
+            if sbrk(incr as i64).is_err() {
+                return ptr::null_mut();
+            }
+            self.end += incr;
+        }
+        self.start = start + size;
+        start as *mut u8
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (index, _) = bin_of(layout);
+        let block = ptr as *mut usize;
+        *block = self.bins[index] as usize;
+        self.bins[index] = block;
+    }
+}
+
+/// A memory allocator for user programs, serving allocations from a heap grown
+/// with `sbrk`.
+///
+/// Allocations are rounded up to a power of two and freed blocks are kept in
+/// a list per size for later allocations of the same size; memory is never
+/// given back to the kernel. The program break must only be moved by this
+/// allocator.
+///
+/// A user program enables it as its global allocator with:
+///
+/// ```rust,ignore
+/// #[global_allocator]
+/// static ALLOCATOR: kernel_api::allocator::Allocator = kernel_api::allocator::Allocator::new();
+/// ```
+pub struct Allocator(UnsafeCell<Heap>);
+
+// User processes have a single thread of execution.
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    /// Returns an allocator with an empty heap, initialized from the program
+    /// break on the first allocation.
+    pub const fn new() -> Allocator {
+        Allocator(UnsafeCell::new(Heap {
+            bins: [ptr::null_mut(); BINS_LEN],
+            start: 0,
+            end: 0,
+        }))
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        (*self.0.get()).alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        (*self.0.get()).dealloc(ptr, layout)
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let heap = unsafe { &*self.0.get() };
+        f.debug_struct("Allocator")
+            .field("start", &heap.start)
+            .field("end", &heap.end)
+            .finish()
+    }
+}
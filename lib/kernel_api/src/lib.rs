@@ -5,6 +5,8 @@ use core::fmt;
 
 use shim::io;
 
+#[cfg(feature = "user-space")]
+pub mod allocator;
 #[cfg(feature = "user-space")]
 pub mod syscall;
 
@@ -22,6 +24,7 @@ pub enum OsError {
     BadAddress = 50,
     FileExists = 60,
     InvalidArgument = 70,
+    BadFileDescriptor = 80,
 
     IoError = 101,
     IoErrorEof = 102,
@@ -46,11 +49,13 @@ impl core::convert::From<u64> for OsError {
             50 => OsError::BadAddress,
             60 => OsError::FileExists,
             70 => OsError::InvalidArgument,
+            80 => OsError::BadFileDescriptor,
 
             101 => OsError::IoError,
             102 => OsError::IoErrorEof,
             103 => OsError::IoErrorInvalidData,
             104 => OsError::IoErrorInvalidInput,
+            105 => OsError::IoErrorTimedOut,
 
             200 => OsError::InvalidSocket,
             201 => OsError::SocketAlreadyOpen,
@@ -69,6 +74,8 @@ impl core::convert::From<io::Error> for OsError {
             io::ErrorKind::InvalidInput => OsError::IoErrorInvalidInput,
             io::ErrorKind::TimedOut => OsError::IoErrorTimedOut,
             io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::AlreadyExists => OsError::FileExists,
+            io::ErrorKind::PermissionDenied => OsError::NoAccess,
             _ => OsError::IoError,
         }
     }
@@ -77,5 +84,40 @@ impl core::convert::From<io::Error> for OsError {
 pub const NR_SLEEP: usize = 1;
 pub const NR_TIME: usize = 2;
 pub const NR_EXIT: usize = 3;
-pub const NR_WRITE: usize = 4;
+pub const NR_WRITE_BYTE: usize = 4;
 pub const NR_GETPID: usize = 5;
+pub const NR_OPEN: usize = 6;
+pub const NR_READ: usize = 7;
+pub const NR_WRITE: usize = 8;
+pub const NR_CLOSE: usize = 9;
+pub const NR_SEEK: usize = 10;
+pub const NR_CONSOLE_WRITE: usize = 11;
+pub const NR_CONSOLE_READ: usize = 12;
+pub const NR_FORK: usize = 13;
+pub const NR_EXEC: usize = 14;
+pub const NR_WAIT: usize = 15;
+pub const NR_BRK: usize = 16;
+pub const NR_MMAP: usize = 17;
+pub const NR_MUNMAP: usize = 18;
+
+/// `open` flag: create the file if it does not exist.
+pub const O_CREAT: u64 = 1 << 0;
+
+/// `seek` origins: from the start, the current position or the end of a file.
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+/// `mmap` protection flags: the memory is readable, writable or executable.
+pub const PROT_READ: u64 = 1 << 0;
+pub const PROT_WRITE: u64 = 1 << 1;
+pub const PROT_EXEC: u64 = 1 << 2;
+
+/// `mmap` flag: map zeroed memory rather than a file.
+pub const MAP_ANONYMOUS: u64 = 1 << 0;
+
+/// `wait` process ID matching any child of the caller.
+pub const WAIT_ANY: u64 = core::u64::MAX;
+
+/// Exit status of a process killed by the kernel after a fault.
+pub const EXIT_KILLED: i32 = -1;
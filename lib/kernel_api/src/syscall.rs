@@ -2,6 +2,8 @@ use core::fmt;
 use core::fmt::Write;
 use core::time::Duration;
 
+use shim::io;
+
 use crate::*;
 
 macro_rules! err_or {
@@ -61,18 +63,21 @@ pub fn time() -> Duration {
     Duration::new(time_secs, time_ns as u32)
 }
 
-pub fn exit() -> ! {
+/// Terminates the calling process with exit status `status`, which its parent
+/// collects with `wait`.
+pub fn exit(status: i32) -> ! {
     unsafe {
-        asm!("svc $0"
-             :
-             : "i"(NR_EXIT)
+        asm!("mov x0, $0
+              svc $1"
              :
+             : "r"(status as u64), "i"(NR_EXIT)
+             : "x0"
              : "volatile");
     }
     loop {}
 }
 
-pub fn write(b: u8) {
+pub fn write_byte(b: u8) {
     let mut ecode: u64;
 
     unsafe {
@@ -80,7 +85,7 @@ pub fn write(b: u8) {
               svc $2
               mov $1, x7"
              : "=r"(ecode)
-             : "r"(b), "i"(NR_WRITE)
+             : "r"(b), "i"(NR_WRITE_BYTE)
              : "x0", "x7"
              : "volatile");
     }
@@ -111,12 +116,343 @@ pub fn getpid() -> u64 {
     pid
 }
 
-struct Console;
+/// Opens the file at the absolute path `path` and returns its file
+/// descriptor. With `O_CREAT` in `flags`, the file is created if it does not
+/// exist.
+pub fn open(path: &str, flags: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut fd: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(fd), "=r"(ecode)
+             : "r"(path.as_ptr()), "r"(path.len()), "r"(flags), "i"(NR_OPEN)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, fd)
+}
+
+/// Reads from the file descriptor `fd` into `buf` and returns the number of
+/// bytes read. Zero is returned at the end of the file.
+pub fn read(fd: u64, buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(len), "=r"(ecode)
+             : "r"(fd), "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_READ)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+/// Writes `buf` to the file descriptor `fd` and returns the number of bytes
+/// written.
+pub fn write(fd: u64, buf: &[u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(len), "=r"(ecode)
+             : "r"(fd), "r"(buf.as_ptr()), "r"(buf.len()), "i"(NR_WRITE)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+/// Closes the file descriptor `fd`.
+pub fn close(fd: u64) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(fd), "i"(NR_CLOSE)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Moves the position of the file descriptor `fd` to `pos` and returns the
+/// new position from the start of the file.
+pub fn seek(fd: u64, pos: io::SeekFrom) -> OsResult<u64> {
+    let (whence, offset) = match pos {
+        io::SeekFrom::Start(n) => (SEEK_SET, n),
+        io::SeekFrom::Current(n) => (SEEK_CUR, n as u64),
+        io::SeekFrom::End(n) => (SEEK_END, n as u64),
+    };
+    let mut ecode: u64;
+    let mut new_pos: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(new_pos), "=r"(ecode)
+             : "r"(fd), "r"(offset), "r"(whence), "i"(NR_SEEK)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, new_pos)
+}
+
+/// Writes `buf` to the console and returns the number of bytes written.
+pub fn console_write(buf: &[u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(len), "=r"(ecode)
+             : "r"(buf.as_ptr()), "r"(buf.len()), "i"(NR_CONSOLE_WRITE)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+/// Reads from the console into `buf`, waiting until at least one byte is
+/// available, and returns the number of bytes read.
+pub fn console_read(buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(len), "=r"(ecode)
+             : "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_CONSOLE_READ)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+/// Creates a copy of the calling process. Returns the ID of the new process
+/// in the calling process, and 0 in the new one.
+pub fn fork() -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut pid: u64;
+
+    unsafe {
+        asm!("svc $2
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(pid), "=r"(ecode)
+             : "i"(NR_FORK)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, pid)
+}
+
+/// Replaces the program of the calling process with the one stored at the
+/// absolute path `path`, started with the arguments `args`. The new program
+/// receives the number of arguments in `x0` and their address, as a `&[&str]`,
+/// in `x1`. Only returns if the program could not be started.
+pub fn exec(path: &str, args: &[&str]) -> OsError {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              mov x2, $3
+              mov x3, $4
+              svc $5
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(path.as_ptr()), "r"(path.len()), "r"(args.as_ptr()), "r"(args.len()),
+               "i"(NR_EXEC)
+             : "x0", "x1", "x2", "x3", "x7"
+             : "volatile");
+    }
+
+    OsError::from(ecode)
+}
+
+/// Waits for the child process `pid` of the calling process to exit, or for
+/// any child if `pid` is `WAIT_ANY`. Returns the ID and the exit status of the
+/// child, which is then reaped.
+pub fn wait(pid: u64) -> OsResult<(u64, i32)> {
+    let mut ecode: u64;
+    let mut child: u64;
+    let mut status: u64;
+
+    unsafe {
+        asm!("mov x0, $3
+              svc $4
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+             : "=r"(child), "=r"(status), "=r"(ecode)
+             : "r"(pid), "i"(NR_WAIT)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, (child, status as i32))
+}
+
+/// Moves the program break, the end of the heap of the calling process, to
+/// `addr` and returns it. If `addr` is 0, returns the break without moving it.
+pub fn brk(addr: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut brk: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              svc $3
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(brk), "=r"(ecode)
+             : "r"(addr), "i"(NR_BRK)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, brk)
+}
+
+/// Moves the program break by `incr` bytes and returns its previous value, the
+/// start of the memory added to the heap when `incr` is positive.
+pub fn sbrk(incr: i64) -> OsResult<u64> {
+    let old = brk(0)?;
+    if incr != 0 {
+        brk((old as i64).wrapping_add(incr) as u64)?;
+    }
+    Ok(old)
+}
+
+/// Maps `len` bytes of memory with the protection flags `prot` (`PROT_*`) and
+/// returns its address. With `MAP_ANONYMOUS` in `flags`, the memory is zeroed.
+/// Otherwise, it is a read-only copy of the file open at `fd` from the page
+/// aligned `offset`.
+pub fn mmap(len: usize, prot: u64, flags: u64, fd: u64, offset: u64) -> OsResult<u64> {
+    let mut ecode: u64;
+    let mut addr: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              mov x3, $5
+              mov x4, $6
+              svc $7
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(addr), "=r"(ecode)
+             : "r"(len), "r"(prot), "r"(flags), "r"(fd), "r"(offset), "i"(NR_MMAP)
+             : "x0", "x1", "x2", "x3", "x4", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, addr)
+}
+
+/// Unmaps the memory mapped with `mmap` in the `len` bytes at the page aligned
+/// address `addr`.
+pub fn munmap(addr: u64, len: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(addr), "r"(len), "i"(NR_MUNMAP)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+/// Size of the output buffer of `print!`.
+const CONSOLE_BUF_SIZE: usize = 256;
+
+/// Formatting sink collecting output into a buffer, so that a `print!` makes
+/// a single `console_write` system call per `CONSOLE_BUF_SIZE` bytes rather
+/// than one per formatted piece.
+struct Console {
+    buf: [u8; CONSOLE_BUF_SIZE],
+    len: usize,
+}
+
+impl Console {
+    fn new() -> Console {
+        Console {
+            buf: [0; CONSOLE_BUF_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Writes the buffered bytes to the console.
+    fn flush(&mut self) -> fmt::Result {
+        let mut written = 0;
+        while written < self.len {
+            match console_write(&self.buf[written..self.len]) {
+                Ok(0) | Err(_) => return Err(fmt::Error),
+                Ok(n) => written += n,
+            }
+        }
+        self.len = 0;
+        Ok(())
+    }
+}
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for b in s.bytes() {
-            write(b);
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            if self.len == CONSOLE_BUF_SIZE {
+                self.flush()?;
+            }
+            let n = core::cmp::min(bytes.len(), CONSOLE_BUF_SIZE - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
         }
         Ok(())
     }
@@ -137,6 +473,7 @@ macro_rules! println {
 }
 
 pub fn vprint(args: fmt::Arguments) {
-    let mut c = Console;
+    let mut c = Console::new();
     c.write_fmt(args).unwrap();
+    c.flush().unwrap();
 }
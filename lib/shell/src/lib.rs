@@ -283,6 +283,52 @@ fn cmd_sleep<'a, T: io::Read + io::Write, F: FileSystem>(
     Ok(())
 }
 
+fn cmd_cachestat<'a, T: io::Read + io::Write, F: FileSystem>(
+    args: &StackVec<'a, &'a str>,
+    cwd: &mut Cwd<F>,
+    rw: &mut T,
+) -> Result<()> {
+    let stats = match cwd.fs.cache_stats() {
+        Some(stats) => stats,
+        None => return ioerr!(Other, "file system has no sector cache"),
+    };
+    let accesses = stats.hits + stats.misses;
+    let hit_rate = if accesses == 0 {
+        0
+    } else {
+        stats.hits * 100 / accesses
+    };
+    writeln!(
+        rw,
+        "cached:     {}/{} sectors",
+        stats.cached, stats.capacity
+    );
+    writeln!(rw, "hits:       {} ({}%)", stats.hits, hit_rate);
+    writeln!(rw, "misses:     {}", stats.misses);
+    writeln!(rw, "evictions:  {}", stats.evictions);
+    writeln!(rw, "writebacks: {}", stats.writebacks);
+    Ok(())
+}
+
+fn cmd_df<'a, T: io::Read + io::Write, F: FileSystem>(
+    args: &StackVec<'a, &'a str>,
+    cwd: &mut Cwd<F>,
+    rw: &mut T,
+) -> Result<()> {
+    let stats = cwd.fs.statfs()?;
+    let total = stats.total_space();
+    let free = stats.free_space();
+    writeln!(
+        rw,
+        "{:?}, {} byte clusters",
+        stats.fat_type, stats.cluster_size
+    );
+    writeln!(rw, "total: {} KiB", total / 1024);
+    writeln!(rw, "used:  {} KiB", (total - free) / 1024);
+    writeln!(rw, "free:  {} KiB", free / 1024);
+    Ok(())
+}
+
 fn run<T: io::Read + io::Write, F: FileSystem>(
     cmd: &Command,
     cwd: &mut Cwd<F>,
@@ -296,6 +342,8 @@ fn run<T: io::Read + io::Write, F: FileSystem>(
         "ls" => cmd_ls(&cmd.args, cwd, rw),
         "cat" => cmd_cat(&cmd.args, cwd, rw),
         "sleep" => cmd_sleep(&cmd.args, cwd, rw),
+        "cachestat" => cmd_cachestat(&cmd.args, cwd, rw),
+        "df" => cmd_df(&cmd.args, cwd, rw),
         "exit" => return ioerr!(Interrupted, "exit"),
         unk => {
             writeln!(rw, "ERR: unknown command: {}", path);
@@ -1,12 +1,23 @@
+use core::alloc::Layout;
 use core::mem::zeroed;
 use core::panic::PanicInfo;
 use core::ptr::write_volatile;
 
+use kernel_api::allocator::Allocator;
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator::new();
+
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
 }
 
+#[alloc_error_handler]
+fn oom(_layout: Layout) -> ! {
+    panic!("OOM");
+}
+
 unsafe fn zeros_bss() {
     extern "C" {
         static mut __bss_beg: u64;
@@ -26,5 +37,5 @@ unsafe fn zeros_bss() {
 pub unsafe extern "C" fn _start() -> ! {
     zeros_bss();
     crate::main();
-    kernel_api::syscall::exit();
+    kernel_api::syscall::exit(0);
 }
@@ -1,4 +1,5 @@
 #![feature(asm)]
+#![feature(alloc_error_handler)]
 #![no_std]
 #![no_main]
 
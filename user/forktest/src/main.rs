@@ -0,0 +1,95 @@
+#![feature(asm)]
+#![feature(alloc_error_handler)]
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod cr0;
+
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+
+use kernel_api::println;
+use kernel_api::syscall::{close, exit, fork, open, read, wait};
+
+/// Number of processes run before measuring, so that kernel structures that
+/// grow once, like the scheduler's queue, are already allocated.
+const WARMUP: usize = 8;
+
+/// Number of processes run between the two measurements.
+const ROUNDS: usize = 200;
+
+/// Returns the number of bytes of kernel heap in use, read from
+/// `/proc/meminfo`.
+fn heap_used() -> usize {
+    let fd = open("/proc/meminfo", 0).expect("open /proc/meminfo");
+    let mut buf = [0u8; 64];
+    let len = read(fd, &mut buf).expect("read /proc/meminfo");
+    close(fd).expect("close /proc/meminfo");
+
+    core::str::from_utf8(&buf[..len])
+        .ok()
+        .and_then(|info| info.split_whitespace().nth(1))
+        .and_then(|used| used.parse().ok())
+        .expect("malformed /proc/meminfo")
+}
+
+/// Forks a child that grows its heap by a few pages and exits with `status`,
+/// then reaps it.
+fn run_child(status: i32) {
+    let pid = fork().expect("fork");
+    if pid == 0 {
+        let data: Vec<u8> = alloc::vec![status as u8; 256 * 1024];
+        exit(data[data.len() - 1] as i32);
+    }
+
+    match wait(pid) {
+        Ok((child, code)) if child == pid && code == status & 0xff => {}
+        result => {
+            println!("forktest: unexpected wait result {:?}", result);
+            exit(1);
+        }
+    }
+}
+
+/// Checks that the writes of a child to the memory it shares with this
+/// process after `fork` are not seen by this process.
+fn check_copy_on_write() {
+    let mut data = alloc::vec![1u8; 1024];
+    let pid = fork().expect("fork");
+    if pid == 0 {
+        unsafe { write_volatile(&mut data[0], 2) };
+        exit(unsafe { read_volatile(&data[0]) } as i32);
+    }
+
+    let (_, status) = wait(pid).expect("wait");
+    if status != 2 || unsafe { read_volatile(&data[0]) } != 1 {
+        println!("forktest: FAILED, memory is not copied on write");
+        exit(1);
+    }
+}
+
+fn main() {
+    check_copy_on_write();
+
+    for i in 0..WARMUP {
+        run_child(i as i32);
+    }
+
+    let before = heap_used();
+    for i in 0..ROUNDS {
+        run_child(i as i32);
+    }
+    let after = heap_used();
+
+    println!(
+        "forktest: {} processes, kernel heap {} -> {} bytes",
+        ROUNDS, before, after
+    );
+    if after > before {
+        println!("forktest: FAILED, the kernel heap grew");
+        exit(1);
+    }
+    println!("forktest: OK");
+}